@@ -0,0 +1,137 @@
+//! Authenticated ciphers used to encrypt the payload
+//!
+//! Both take a 256-bit key and append a 16 byte tag, and only differ by the length of their
+//! nonce. Nonces are random, which for the 96-bit nonces of AES-256-GCM limits how many payloads
+//! can safely share a key, while the 192-bit nonces of XChaCha20-Poly1305 never collide in
+//! practice
+
+use super::Cipher;
+use super::Error;
+use super::Plaintext;
+
+impl Default for Cipher {
+    /// [`Aes256Gcm`](enum.Cipher.html#variant.Aes256Gcm), which every version can read
+    fn default() -> Self {
+        Self::Aes256Gcm
+    }
+}
+
+impl Cipher {
+    /// Length of the nonce stored in the header
+    pub(crate) fn nonce_len(self) -> usize {
+        match self {
+            Self::Aes256Gcm => super::header::NONCE_LEN,
+            Self::XChaCha20Poly1305 => 24,
+        }
+    }
+
+    /// Encrypts `msg`, authenticating `aad` along with it
+    ///
+    /// The nonce must be [`nonce_len`](#method.nonce_len) bytes long
+    pub(crate) fn encrypt(
+        self,
+        key: &[u8],
+        nonce: &[u8],
+        msg: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        use aes_gcm::aead::generic_array::GenericArray;
+        use aes_gcm::aead::{Aead, KeyInit, Payload};
+
+        let payload = Payload { msg, aad };
+        match self {
+            Self::Aes256Gcm => aes_gcm::Aes256Gcm::new(GenericArray::from_slice(key))
+                .encrypt(GenericArray::from_slice(nonce), payload),
+            Self::XChaCha20Poly1305 => {
+                chacha20poly1305::XChaCha20Poly1305::new(GenericArray::from_slice(key))
+                    .encrypt(GenericArray::from_slice(nonce), payload)
+            }
+        }
+        .map_err(Error::Crypto)
+    }
+
+    /// Decrypts `msg`, failing unless both it and `aad` are authentic
+    ///
+    /// The nonce must be [`nonce_len`](#method.nonce_len) bytes long
+    pub(crate) fn decrypt(
+        self,
+        key: &[u8],
+        nonce: &[u8],
+        msg: &[u8],
+        aad: &[u8],
+    ) -> Result<Plaintext, Error> {
+        use aes_gcm::aead::generic_array::GenericArray;
+        use aes_gcm::aead::{Aead, KeyInit, Payload};
+
+        let payload = Payload { msg, aad };
+        match self {
+            Self::Aes256Gcm => aes_gcm::Aes256Gcm::new(GenericArray::from_slice(key))
+                .decrypt(GenericArray::from_slice(nonce), payload),
+            Self::XChaCha20Poly1305 => {
+                chacha20poly1305::XChaCha20Poly1305::new(GenericArray::from_slice(key))
+                    .decrypt(GenericArray::from_slice(nonce), payload)
+            }
+        }
+        .map(Plaintext::new)
+        .map_err(Error::Crypto)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cipher;
+
+    const ALL: [Cipher; 2] = [Cipher::Aes256Gcm, Cipher::XChaCha20Poly1305];
+
+    #[test]
+    fn round_trip() {
+        for cipher in &ALL {
+            let nonce = vec![2; cipher.nonce_len()];
+            let sealed = cipher
+                .encrypt(&[1; 32], &nonce, b"payload", b"aad")
+                .unwrap();
+            assert_eq!(sealed.len(), b"payload".len() + super::super::TAG_LEN);
+            assert_eq!(
+                *cipher.decrypt(&[1; 32], &nonce, &sealed, b"aad").unwrap(),
+                b"payload"
+            );
+
+            assert!(cipher.decrypt(&[3; 32], &nonce, &sealed, b"aad").is_err());
+            assert!(cipher.decrypt(&[1; 32], &nonce, &sealed, b"other").is_err());
+        }
+    }
+
+    // From the XChaCha20-Poly1305 draft, draft-irtf-cfrg-xchacha-03, section A.3.1
+    #[test]
+    fn xchacha20poly1305_vector() {
+        let key = hex::decode("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f")
+            .unwrap();
+        let nonce = hex::decode("404142434445464748494a4b4c4d4e4f5051525354555657").unwrap();
+        let aad = hex::decode("50515253c0c1c2c3c4c5c6c7").unwrap();
+        let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you only one \
+                          tip for the future, sunscreen would be it.";
+
+        let sealed = Cipher::XChaCha20Poly1305
+            .encrypt(&key, &nonce, plaintext, &aad)
+            .unwrap();
+        assert_eq!(
+            hex::encode(&sealed[..plaintext.len()]),
+            concat!(
+                "bd6d179d3e83d43b9576579493c0e939572a1700252bfaccbed2902c21396cbb",
+                "731c7f1b0b4aa6440bf3a82f4eda7e39ae64c6708c54c216cb96b72e1213b452",
+                "2f8c9ba40db5d945b11b69b982c1bb9e3f3fac2bc369488f76b2383565d3fff9",
+                "21f9664c97637da9768812f615c68b13b52e"
+            )
+        );
+        assert_eq!(
+            hex::encode(&sealed[plaintext.len()..]),
+            "c0875924c1c7987947deafd8780acf49"
+        );
+        assert_eq!(
+            *Cipher::XChaCha20Poly1305
+                .decrypt(&key, &nonce, &sealed, &aad)
+                .unwrap(),
+            &plaintext[..]
+        );
+    }
+}
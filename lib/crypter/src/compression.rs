@@ -0,0 +1,217 @@
+//! Compression applied to the serialized payload before encryption
+//!
+//! All codecs are always compiled in, so any build can read payloads written by any other build
+//!
+//! Output buffers are wiped when they grow or are dropped, but the codecs keep their own window
+//! of recent data in state that cannot be reached, and that state is not wiped
+
+use super::Compression;
+use super::Error;
+use super::Plaintext;
+
+impl Default for Compression {
+    /// [`Deflate`](enum.Compression.html#variant.Deflate) if the `compress` feature is enabled,
+    /// [`None`](enum.Compression.html#variant.None) otherwise
+    fn default() -> Self {
+        if cfg!(feature = "compress") {
+            Self::Deflate
+        } else {
+            Self::None
+        }
+    }
+}
+
+impl Compression {
+    pub(crate) fn compress(self, data: Plaintext) -> Plaintext {
+        match self {
+            Self::None => data,
+            Self::Deflate => deflate(&data),
+            Self::Zstd => {
+                let mut output = Output(Plaintext::default());
+                ruzstd::encoding::compress(
+                    data.as_slice(),
+                    &mut output,
+                    ruzstd::encoding::CompressionLevel::Fastest,
+                );
+                output.0
+            }
+        }
+    }
+
+    // Allowed because the returned errors are quite useless, just a number or a decoder state
+    #[allow(clippy::map_err_ignore)]
+    pub(crate) fn decompress(self, data: Plaintext) -> Result<Plaintext, Error> {
+        match self {
+            Self::None => Ok(data),
+            Self::Deflate => inflate(&data),
+            Self::Zstd => {
+                use std::io::Read;
+
+                let mut decoder = ruzstd::decoding::StreamingDecoder::new(data.as_slice())
+                    .map_err(|_| Error::Inflation)?;
+                let mut output = Plaintext::default();
+                let mut chunk = Plaintext::new(vec![0; 8 * 1024]);
+                loop {
+                    let read = decoder.read(&mut chunk).map_err(|_| Error::Inflation)?;
+                    if read == 0 {
+                        break Ok(output);
+                    }
+                    reserve(&mut output, read);
+                    output.extend_from_slice(&chunk[..read]);
+                }
+            }
+        }
+    }
+}
+
+/// Raw DEFLATE at level 8, the same stream as `miniz_oxide::deflate::compress_to_vec`
+fn deflate(data: &[u8]) -> Plaintext {
+    use miniz_oxide::deflate::core::{
+        compress, create_comp_flags_from_zip_params, CompressorOxide, TDEFLFlush, TDEFLStatus,
+    };
+
+    let mut compressor = CompressorOxide::new(create_comp_flags_from_zip_params(8, 0, 0));
+    let mut output = Plaintext::new(vec![0; std::cmp::max(data.len() / 2, 2)]);
+    let mut in_pos = 0;
+    let mut out_pos = 0;
+    loop {
+        let (status, consumed, written) = compress(
+            &mut compressor,
+            &data[in_pos..],
+            &mut output[out_pos..],
+            TDEFLFlush::Finish,
+        );
+        in_pos += consumed;
+        out_pos += written;
+
+        match status {
+            TDEFLStatus::Done => {
+                output.truncate(out_pos);
+                break output;
+            }
+            TDEFLStatus::Okay => {
+                if output.len() - out_pos < 30 {
+                    let len = output.len();
+                    resize(&mut output, len * 2);
+                }
+            }
+            _ => unreachable!("the compressor only fails on bad parameters"),
+        }
+    }
+}
+
+/// Raw DEFLATE, the same as `miniz_oxide::inflate::decompress_to_vec`
+fn inflate(data: &[u8]) -> Result<Plaintext, Error> {
+    use miniz_oxide::inflate::core::{decompress, inflate_flags, DecompressorOxide};
+    use miniz_oxide::inflate::TINFLStatus;
+
+    let mut decompressor = DecompressorOxide::new();
+    let mut output = Plaintext::new(vec![0; data.len().saturating_mul(2)]);
+    let mut in_pos = 0;
+    let mut out_pos = 0;
+    loop {
+        // The whole output is passed on every call so that matches can reach back into it
+        let (status, consumed, written) = decompress(
+            &mut decompressor,
+            &data[in_pos..],
+            &mut output,
+            out_pos,
+            inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+        );
+        in_pos += consumed;
+        out_pos += written;
+
+        match status {
+            TINFLStatus::Done => {
+                output.truncate(out_pos);
+                break Ok(output);
+            }
+            TINFLStatus::HasMoreOutput => {
+                let len = output.len().checked_add(out_pos).ok_or(Error::Inflation)?;
+                resize(&mut output, len);
+            }
+            _ => break Err(Error::Inflation),
+        }
+    }
+}
+
+/// Zero-fills `buffer` up to `len`, moving it to a new allocation so the old one is wiped on drop
+/// instead of being left behind by the reallocation
+fn resize(buffer: &mut Plaintext, len: usize) {
+    let mut resized = Plaintext::new(vec![0; len]);
+    resized[..buffer.len()].copy_from_slice(buffer);
+    *buffer = resized;
+}
+
+/// Makes room for `additional` bytes the same way as [`resize`], without changing the length
+fn reserve(buffer: &mut Plaintext, additional: usize) {
+    let needed = buffer.len() + additional;
+    if needed > buffer.capacity() {
+        let mut grown = Plaintext::new(Vec::with_capacity(std::cmp::max(
+            needed,
+            buffer.capacity() * 2,
+        )));
+        grown.extend_from_slice(buffer);
+        *buffer = grown;
+    }
+}
+
+/// Sink for the zstd encoder that never leaves a copy behind when it grows
+struct Output(Plaintext);
+
+impl std::io::Write for Output {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        reserve(&mut self.0, data.len());
+        self.0.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Compression;
+
+    const ALL: [Compression; 3] = [Compression::None, Compression::Deflate, Compression::Zstd];
+
+    #[test]
+    fn round_trip() {
+        let data = b"foobar".repeat(100);
+        for compression in &ALL {
+            let compressed = compression.compress(data.clone().into());
+            assert_eq!(*compression.decompress(compressed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn compresses() {
+        let data = b"foobar".repeat(100);
+        assert!(Compression::Deflate.compress(data.clone().into()).len() < data.len());
+        assert!(Compression::Zstd.compress(data.clone().into()).len() < data.len());
+    }
+
+    #[test]
+    fn matches_miniz() {
+        let data = b"foobar".repeat(1000);
+        let compressed = Compression::Deflate.compress(data.clone().into());
+        assert_eq!(*compressed, miniz_oxide::deflate::compress_to_vec(&data, 8));
+        assert_eq!(
+            *Compression::Deflate.decompress(compressed).unwrap(),
+            miniz_oxide::inflate::decompress_to_vec(&miniz_oxide::deflate::compress_to_vec(
+                &data, 8
+            ))
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn corrupt() {
+        assert!(Compression::Deflate
+            .decompress(vec![0xff; 16].into())
+            .is_err());
+        assert!(Compression::Zstd.decompress(vec![0xff; 16].into()).is_err());
+    }
+}
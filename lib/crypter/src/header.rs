@@ -0,0 +1,480 @@
+//! Self-describing container header written in front of every encrypted payload
+//!
+//! Layout of the current version, with all integers in little endian:
+//!
+//! | Field         | Size                               |
+//! |---------------|------------------------------------|
+//! | Magic         | 4 (`PSFR`)                         |
+//! | Version       | 1                                  |
+//! | Cipher        | 1                                  |
+//! | Compression   | 1                                  |
+//! | Serialization | 1                                  |
+//! | Flags         | 2                                  |
+//! | KDF           | 1 + parameters                     |
+//! | Salt          | [`SALT_LEN`](../constant.SALT_LEN.html) |
+//! | Nonce         | 12, or 24 with XChaCha20-Poly1305  |
+//! | Wrapped key   | 48, only with the wrapped key flag |
+//! | Recipients    | 2 + 112 per recipient, only with the recipients flag |
+//!
+//! Payloads encrypted with a passphrase carry their data key wrapped with the key derived from
+//! it, using the same nonce. Payloads encrypted to recipients carry the wrapped data key of each
+//! recipient instead, and the KDF and salt are left unused. Payloads with neither flag are
+//! encrypted with the derived key directly
+//!
+//! The whole header is authenticated as associated data of the AEAD, so tampering with any of
+//! the fields fails decryption
+//!
+//! Payloads without the magic bytes are legacy payloads, reported as version `0`
+
+use super::Error;
+use super::Kdf;
+use super::Stanza;
+use super::SALT_LEN;
+use super::WRAPPED_LEN;
+
+/// Marks a payload that carries a header
+const MAGIC: &[u8; 4] = b"PSFR";
+
+/// Version written by this library
+pub const VERSION: u8 = 2;
+
+/// Length of the nonce of AES-256-GCM, the only cipher of payloads before version `2`
+pub(crate) const NONCE_LEN: usize = 12;
+
+/// Flag of payloads encrypted to recipients rather than with a passphrase
+pub(crate) const RECIPIENTS: u16 = 1;
+
+/// Flag of payloads encrypted with a data key wrapped with the passphrase
+pub(crate) const WRAPPED_KEY: u16 = 2;
+
+/// Flags understood by this library, any other set bit is rejected
+const KNOWN_FLAGS: u16 = RECIPIENTS | WRAPPED_KEY;
+
+macro_rules! algorithm {
+    ($(#[$meta:meta])* $name:ident, $kind:literal, { $($(#[$variant_meta:meta])* $variant:ident = $id:literal),* $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum $name {
+            $($(#[$variant_meta])* $variant),*
+        }
+
+        impl $name {
+            fn id(self) -> u8 {
+                match self {
+                    $(Self::$variant => $id),*
+                }
+            }
+
+            fn from_id(id: u8) -> Result<Self, Error> {
+                match id {
+                    $($id => Ok(Self::$variant),)*
+                    _ => Err(Error::UnsupportedAlgorithm { kind: $kind, id }),
+                }
+            }
+        }
+    };
+}
+
+algorithm!(
+    /// Authenticated cipher used to encrypt the payload
+    Cipher, "cipher", {
+        /// AES-256 in Galois/Counter Mode
+        Aes256Gcm = 0,
+        /// `ChaCha20` with extended nonces, and Poly1305, which is fast without AES hardware support
+        XChaCha20Poly1305 = 1,
+    }
+);
+
+algorithm!(
+    /// Compression applied to the serialized payload before encryption
+    Compression, "compression", {
+        /// No compression
+        None = 0,
+        /// DEFLATE
+        Deflate = 1,
+        /// Zstandard
+        Zstd = 2,
+    }
+);
+
+algorithm!(
+    /// Format the payload is serialized into
+    Serialization, "serialization", {
+        /// `MessagePack`
+        MessagePack = 0,
+    }
+);
+
+/// Describes how a payload was produced
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    /// Format version
+    pub version: u8,
+    /// Cipher used for encryption
+    pub cipher: Cipher,
+    /// Derivation of the key from the passphrase
+    pub kdf: Kdf,
+    /// Compression applied before encryption
+    ///
+    /// Versions before `2` did not record it and report [`None`](enum.Compression.html#variant.None),
+    /// leaving decryption to detect it
+    pub compression: Compression,
+    /// Serialization of the payload
+    pub serialization: Serialization,
+    /// Feature flags
+    pub flags: u16,
+    /// Salt for the key derivation
+    pub salt: [u8; SALT_LEN],
+    /// Nonce for the cipher, of the length it requires
+    pub nonce: Vec<u8>,
+    /// Data key wrapped with the passphrase, when the wrapped key flag is set
+    pub wrapped_key: Option<[u8; WRAPPED_LEN]>,
+    /// Data key wrapped for each recipient, when the recipients flag is set
+    pub recipients: Vec<Stanza>,
+}
+
+impl Header {
+    /// Parses the header at the start of an encrypted payload
+    ///
+    /// # Errors
+    /// * Unknown versions: [`UnsupportedVersion`](enum.Error.html#variant.UnsupportedVersion)
+    /// * Unknown algorithms: [`UnsupportedAlgorithm`](enum.Error.html#variant.UnsupportedAlgorithm)
+    /// * Unknown flags: [`UnsupportedFlags`](enum.Error.html#variant.UnsupportedFlags)
+    /// * Header cut short: [`Truncated`](enum.Error.html#variant.Truncated)
+    /// * Inconsistent header: [`Malformed`](enum.Error.html#variant.Malformed)
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        Self::split(data).map(|(header, _, _)| header)
+    }
+
+    /// Parses the header, returning it along with the bytes to authenticate and the ciphertext
+    pub(crate) fn split(data: &[u8]) -> Result<(Self, &[u8], &[u8]), Error> {
+        let Some(body) = data.strip_prefix(&MAGIC[..]) else {
+            return Self::split_legacy(data);
+        };
+
+        let (version, body) = body.split_first().ok_or(Error::Truncated)?;
+        let (header, rest) = match *version {
+            2 => Self::read_v2(body)?,
+            version => return Err(Error::UnsupportedVersion(version)),
+        };
+
+        Ok((header, &data[..data.len() - rest.len()], rest))
+    }
+
+    /// Appends the header to `output`
+    ///
+    /// Always writes the current [`VERSION`](constant.VERSION.html)
+    pub(crate) fn write(&self, output: &mut Vec<u8>) {
+        output.extend_from_slice(MAGIC);
+        output.push(VERSION);
+        output.push(self.cipher.id());
+        output.push(self.compression.id());
+        output.push(self.serialization.id());
+        output.extend_from_slice(&self.flags.to_le_bytes());
+        self.kdf.write(output);
+        output.extend_from_slice(&self.salt);
+        output.extend_from_slice(&self.nonce);
+
+        if self.flags & WRAPPED_KEY != 0 {
+            output.extend_from_slice(&self.wrapped_key.unwrap_or([0; WRAPPED_LEN]));
+        }
+
+        if self.flags & RECIPIENTS != 0 {
+            use std::convert::TryFrom;
+
+            let count = u16::try_from(self.recipients.len()).unwrap_or(u16::MAX);
+            output.extend_from_slice(&count.to_le_bytes());
+            for stanza in self.recipients.iter().take(usize::from(count)) {
+                stanza.write(output);
+            }
+        }
+    }
+
+    fn split_legacy(data: &[u8]) -> Result<(Self, &[u8], &[u8]), Error> {
+        if data.len() < NONCE_LEN {
+            return Err(Error::Truncated);
+        }
+
+        let (nonce, rest) = data.split_at(NONCE_LEN);
+        let header = Self {
+            version: 0,
+            cipher: Cipher::Aes256Gcm,
+            kdf: Kdf::Legacy,
+            compression: Compression::None,
+            serialization: Serialization::MessagePack,
+            flags: 0,
+            salt: [0; SALT_LEN],
+            nonce: nonce.to_vec(),
+            wrapped_key: None,
+            recipients: Vec::new(),
+        };
+
+        Ok((header, &[], rest))
+    }
+
+    fn read_v2(body: &[u8]) -> Result<(Self, &[u8]), Error> {
+        if body.len() < 5 {
+            return Err(Error::Truncated);
+        }
+
+        let cipher = Cipher::from_id(body[0])?;
+        let compression = Compression::from_id(body[1])?;
+        let serialization = Serialization::from_id(body[2])?;
+        let flags = u16::from_le_bytes([body[3], body[4]]);
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(Error::UnsupportedFlags(flags));
+        }
+        if flags & RECIPIENTS != 0 && flags & WRAPPED_KEY != 0 {
+            return Err(Error::Malformed);
+        }
+
+        let (kdf, body) = Kdf::read(&body[5..])?;
+
+        let mut header = Self {
+            version: 2,
+            cipher,
+            kdf,
+            compression,
+            serialization,
+            flags,
+            salt: [0; SALT_LEN],
+            nonce: Vec::new(),
+            wrapped_key: None,
+            recipients: Vec::new(),
+        };
+        let mut rest = header.read_salt_and_nonce(body)?;
+
+        if flags & WRAPPED_KEY != 0 {
+            if rest.len() < WRAPPED_LEN {
+                return Err(Error::Truncated);
+            }
+            let mut wrapped_key = [0; WRAPPED_LEN];
+            wrapped_key.copy_from_slice(&rest[..WRAPPED_LEN]);
+            header.wrapped_key = Some(wrapped_key);
+            rest = &rest[WRAPPED_LEN..];
+        }
+
+        if flags & RECIPIENTS != 0 {
+            if rest.len() < 2 {
+                return Err(Error::Truncated);
+            }
+            let count = u16::from_le_bytes([rest[0], rest[1]]);
+            if count == 0 {
+                return Err(Error::Malformed);
+            }
+
+            rest = &rest[2..];
+            for _ in 0..count {
+                let (stanza, remaining) = Stanza::read(rest)?;
+                header.recipients.push(stanza);
+                rest = remaining;
+            }
+        }
+
+        Ok((header, rest))
+    }
+
+    fn read_salt_and_nonce<'a>(&mut self, body: &'a [u8]) -> Result<&'a [u8], Error> {
+        // The unsalted derivation is only valid for payloads without a header
+        if self.kdf == Kdf::Legacy {
+            return Err(Error::Malformed);
+        }
+
+        let nonce_len = self.cipher.nonce_len();
+        if body.len() < SALT_LEN + nonce_len {
+            return Err(Error::Truncated);
+        }
+
+        let (salt, body) = body.split_at(SALT_LEN);
+        let (nonce, rest) = body.split_at(nonce_len);
+        self.salt.copy_from_slice(salt);
+        self.nonce = nonce.to_vec();
+        Ok(rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cipher, Compression, Error, Header, Serialization};
+
+    fn header() -> Header {
+        Header {
+            version: super::VERSION,
+            cipher: Cipher::Aes256Gcm,
+            kdf: super::Kdf::Pbkdf2 { rounds: 16 },
+            compression: Compression::Deflate,
+            serialization: Serialization::MessagePack,
+            flags: 0,
+            salt: [1; super::SALT_LEN],
+            nonce: vec![2; super::NONCE_LEN],
+            wrapped_key: None,
+            recipients: Vec::new(),
+        }
+    }
+
+    fn wrapped_key_header() -> Header {
+        Header {
+            flags: super::WRAPPED_KEY,
+            wrapped_key: Some([5; super::WRAPPED_LEN]),
+            ..header()
+        }
+    }
+
+    fn xchacha_header() -> Header {
+        Header {
+            cipher: Cipher::XChaCha20Poly1305,
+            nonce: vec![2; 24],
+            ..wrapped_key_header()
+        }
+    }
+
+    fn recipients_header() -> Header {
+        let identity = crate::Identity::generate();
+        Header {
+            flags: super::RECIPIENTS,
+            recipients: vec![
+                crate::Stanza::wrap(&[3; 32], identity.recipient()).unwrap(),
+                crate::Stanza::wrap(&[4; 32], identity.recipient()).unwrap(),
+            ],
+            ..header()
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for header in &[header(), xchacha_header()] {
+            let mut bytes = Vec::new();
+            header.write(&mut bytes);
+            let header_len = bytes.len();
+            bytes.extend_from_slice(b"ciphertext");
+
+            let (parsed, associated_data, rest) = Header::split(&bytes).unwrap();
+            assert_eq!(&parsed, header);
+            assert_eq!(associated_data, &bytes[..header_len]);
+            assert_eq!(rest, b"ciphertext");
+        }
+    }
+
+    #[test]
+    fn round_trip_wrapped_key() {
+        let header = wrapped_key_header();
+        let mut bytes = Vec::new();
+        header.write(&mut bytes);
+        bytes.extend_from_slice(b"ciphertext");
+
+        let (parsed, _, rest) = Header::split(&bytes).unwrap();
+        assert_eq!(parsed, header);
+        assert_eq!(rest, b"ciphertext");
+
+        let mut bytes = Vec::new();
+        Header {
+            flags: super::WRAPPED_KEY | super::RECIPIENTS,
+            ..recipients_header()
+        }
+        .write(&mut bytes);
+        assert!(matches!(Header::parse(&bytes), Err(Error::Malformed)));
+    }
+
+    #[test]
+    fn round_trip_recipients() {
+        let header = recipients_header();
+        let mut bytes = Vec::new();
+        header.write(&mut bytes);
+        bytes.extend_from_slice(b"ciphertext");
+
+        let (parsed, _, rest) = Header::split(&bytes).unwrap();
+        assert_eq!(parsed, header);
+        assert_eq!(rest, b"ciphertext");
+
+        let mut bytes = Vec::new();
+        Header {
+            recipients: Vec::new(),
+            ..header
+        }
+        .write(&mut bytes);
+        assert!(matches!(Header::parse(&bytes), Err(Error::Malformed)));
+    }
+
+    #[test]
+    fn legacy() {
+        let bytes = [3_u8; 20];
+        let (parsed, associated_data, rest) = Header::split(&bytes).unwrap();
+        assert_eq!(parsed.version, 0);
+        assert_eq!(parsed.kdf, super::Kdf::Legacy);
+        assert_eq!(parsed.nonce, [3; super::NONCE_LEN]);
+        assert!(associated_data.is_empty());
+        assert_eq!(rest, [3; 8]);
+    }
+
+    #[test]
+    fn truncated() {
+        for header in &[
+            header(),
+            wrapped_key_header(),
+            xchacha_header(),
+            recipients_header(),
+        ] {
+            let mut bytes = Vec::new();
+            header.write(&mut bytes);
+
+            for len in 0..bytes.len() {
+                assert!(matches!(
+                    Header::parse(&bytes[..len]),
+                    Err(Error::Truncated)
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn unsalted() {
+        let mut bytes = Vec::new();
+        Header {
+            kdf: super::Kdf::Legacy,
+            ..header()
+        }
+        .write(&mut bytes);
+
+        assert!(matches!(Header::parse(&bytes), Err(Error::Malformed)));
+    }
+
+    #[test]
+    fn unsupported_version() {
+        for version in &[1, 200] {
+            let mut bytes = Vec::new();
+            header().write(&mut bytes);
+            bytes[4] = *version;
+
+            assert!(matches!(
+                Header::parse(&bytes),
+                Err(Error::UnsupportedVersion(found)) if found == *version
+            ));
+        }
+    }
+
+    #[test]
+    fn unsupported_algorithm() {
+        for index in 5..8 {
+            let mut bytes = Vec::new();
+            header().write(&mut bytes);
+            bytes[index] = 200;
+
+            assert!(matches!(
+                Header::parse(&bytes),
+                Err(Error::UnsupportedAlgorithm { id: 200, .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn unsupported_flags() {
+        let mut bytes = Vec::new();
+        header().write(&mut bytes);
+        bytes[8] = 4;
+
+        assert!(matches!(
+            Header::parse(&bytes),
+            Err(Error::UnsupportedFlags(4))
+        ));
+    }
+}
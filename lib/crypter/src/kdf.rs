@@ -0,0 +1,328 @@
+//! Password-based key derivation
+//!
+//! The derived key is always 32 bytes long, matching the AES-256 key size
+
+use super::Error;
+
+/// Length of the random salt generated for every encryption
+pub const SALT_LEN: usize = 16;
+
+/// Length of the derived key
+pub(crate) const KEY_LEN: usize = 32;
+
+const LEGACY_ID: u8 = 0;
+const ARGON2ID_ID: u8 = 1;
+const SCRYPT_ID: u8 = 2;
+const PBKDF2_ID: u8 = 3;
+
+/// Upper bound on the memory any derivation may use, in bytes
+///
+/// The parameters are read from the payload before it can be authenticated, so they must be
+/// bounded to keep a corrupt or hostile payload from exhausting the machine
+const MAX_MEMORY: u64 = 2 * 1024 * 1024 * 1024;
+
+/// Upper bound on the Argon2id passes
+const MAX_ARGON2_ITERATIONS: u32 = 256;
+
+/// Upper bound on the Argon2id and scrypt parallelism
+const MAX_PARALLELISM: u32 = 64;
+
+/// Upper bound on the PBKDF2 rounds
+const MAX_PBKDF2_ROUNDS: u32 = 100_000_000;
+
+/// Key derivation function and its cost parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kdf {
+    /// Single unsalted SHA-256 digest of the passphrase
+    ///
+    /// Only used to decrypt stores written before salted key derivation was introduced
+    Legacy,
+
+    /// Argon2id
+    Argon2id {
+        /// Memory size in KiB
+        memory: u32,
+        /// Number of passes
+        iterations: u32,
+        /// Degree of parallelism
+        parallelism: u32,
+    },
+
+    /// scrypt
+    Scrypt {
+        /// Base-2 logarithm of the CPU/memory cost
+        log_n: u8,
+        /// Block size
+        r: u32,
+        /// Parallelization
+        p: u32,
+    },
+
+    /// PBKDF2 with HMAC-SHA256
+    Pbkdf2 {
+        /// Number of rounds
+        rounds: u32,
+    },
+}
+
+impl Default for Kdf {
+    fn default() -> Self {
+        Self::Argon2id {
+            memory: argon2::Params::DEFAULT_M_COST,
+            iterations: argon2::Params::DEFAULT_T_COST,
+            parallelism: argon2::Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl Kdf {
+    /// Derives a key from the passphrase and salt
+    ///
+    /// The salt is ignored for the [`Legacy`](enum.Kdf.html#variant.Legacy) derivation
+    // Allowed because the returned errors only describe which parameter was out of range
+    #[allow(clippy::map_err_ignore)]
+    pub(crate) fn derive(self, passphrase: &[u8], salt: &[u8]) -> Result<super::Key, Error> {
+        if !self.within_limits() {
+            return Err(Error::KeyDerivation);
+        }
+
+        let mut key = super::Key::default();
+
+        match self {
+            Self::Legacy => {
+                use sha2::Digest;
+                key.copy_from_slice(&sha2::Sha256::digest(passphrase));
+            }
+            Self::Argon2id {
+                memory,
+                iterations,
+                parallelism,
+            } => {
+                let params = argon2::Params::new(memory, iterations, parallelism, Some(KEY_LEN))
+                    .map_err(|_| Error::KeyDerivation)?;
+                argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+                    .hash_password_into(passphrase, salt, &mut *key)
+                    .map_err(|_| Error::KeyDerivation)?;
+            }
+            Self::Scrypt { log_n, r, p } => {
+                let params =
+                    scrypt::Params::new(log_n, r, p, KEY_LEN).map_err(|_| Error::KeyDerivation)?;
+                scrypt::scrypt(passphrase, salt, &params, &mut *key)
+                    .map_err(|_| Error::KeyDerivation)?;
+            }
+            Self::Pbkdf2 { rounds } => {
+                if rounds == 0 {
+                    return Err(Error::KeyDerivation);
+                }
+                pbkdf2::pbkdf2_hmac::<sha2::Sha256>(passphrase, salt, rounds, &mut *key);
+            }
+        }
+
+        Ok(key)
+    }
+
+    fn within_limits(self) -> bool {
+        match self {
+            Self::Legacy => true,
+            Self::Argon2id {
+                memory,
+                iterations,
+                parallelism,
+            } => {
+                u64::from(memory) * 1024 <= MAX_MEMORY
+                    && iterations <= MAX_ARGON2_ITERATIONS
+                    && parallelism <= MAX_PARALLELISM
+            }
+            Self::Scrypt { log_n, r, p } => {
+                log_n < 64
+                    && 128_u64
+                        .checked_mul(u64::from(r))
+                        .and_then(|block| block.checked_mul(1 << log_n))
+                        .is_some_and(|memory| memory <= MAX_MEMORY)
+                    && p <= MAX_PARALLELISM
+            }
+            Self::Pbkdf2 { rounds } => rounds <= MAX_PBKDF2_ROUNDS,
+        }
+    }
+
+    /// Appends the identifier and parameters to `output`
+    pub(crate) fn write(self, output: &mut Vec<u8>) {
+        match self {
+            Self::Legacy => output.push(LEGACY_ID),
+            Self::Argon2id {
+                memory,
+                iterations,
+                parallelism,
+            } => {
+                output.push(ARGON2ID_ID);
+                output.extend_from_slice(&memory.to_le_bytes());
+                output.extend_from_slice(&iterations.to_le_bytes());
+                output.extend_from_slice(&parallelism.to_le_bytes());
+            }
+            Self::Scrypt { log_n, r, p } => {
+                output.push(SCRYPT_ID);
+                output.push(log_n);
+                output.extend_from_slice(&r.to_le_bytes());
+                output.extend_from_slice(&p.to_le_bytes());
+            }
+            Self::Pbkdf2 { rounds } => {
+                output.push(PBKDF2_ID);
+                output.extend_from_slice(&rounds.to_le_bytes());
+            }
+        }
+    }
+
+    /// Reads the identifier and parameters written by [`write`](#method.write), returning the
+    /// remaining bytes
+    pub(crate) fn read(input: &[u8]) -> Result<(Self, &[u8]), Error> {
+        fn u32_at(input: &[u8], index: usize) -> Result<u32, Error> {
+            let mut bytes = [0_u8; 4];
+            bytes.copy_from_slice(input.get(index..index + 4).ok_or(Error::Truncated)?);
+            Ok(u32::from_le_bytes(bytes))
+        }
+
+        let (id, input) = input.split_first().ok_or(Error::Truncated)?;
+        match *id {
+            LEGACY_ID => Ok((Self::Legacy, input)),
+            ARGON2ID_ID => Ok((
+                Self::Argon2id {
+                    memory: u32_at(input, 0)?,
+                    iterations: u32_at(input, 4)?,
+                    parallelism: u32_at(input, 8)?,
+                },
+                &input[12..],
+            )),
+            SCRYPT_ID => {
+                let (log_n, input) = input.split_first().ok_or(Error::Truncated)?;
+                Ok((
+                    Self::Scrypt {
+                        log_n: *log_n,
+                        r: u32_at(input, 0)?,
+                        p: u32_at(input, 4)?,
+                    },
+                    &input[8..],
+                ))
+            }
+            PBKDF2_ID => Ok((
+                Self::Pbkdf2 {
+                    rounds: u32_at(input, 0)?,
+                },
+                &input[4..],
+            )),
+            id => Err(Error::UnsupportedAlgorithm {
+                kind: "key derivation",
+                id,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Kdf;
+
+    const CHEAP: [Kdf; 3] = [
+        Kdf::Argon2id {
+            memory: 64,
+            iterations: 1,
+            parallelism: 1,
+        },
+        Kdf::Scrypt {
+            log_n: 4,
+            r: 8,
+            p: 1,
+        },
+        Kdf::Pbkdf2 { rounds: 16 },
+    ];
+
+    #[test]
+    fn salt_changes_key() {
+        for kdf in &CHEAP {
+            assert_ne!(
+                kdf.derive(b"foobar", b"salt-salt-salt-1").unwrap(),
+                kdf.derive(b"foobar", b"salt-salt-salt-2").unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn deterministic() {
+        for kdf in &CHEAP {
+            assert_eq!(
+                kdf.derive(b"foobar", b"salt-salt-salt-1").unwrap(),
+                kdf.derive(b"foobar", b"salt-salt-salt-1").unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        assert!(Kdf::Pbkdf2 { rounds: 0 }
+            .derive(b"foobar", b"salt")
+            .is_err());
+        assert!(Kdf::Scrypt {
+            log_n: 200,
+            r: 8,
+            p: 1
+        }
+        .derive(b"foobar", b"salt")
+        .is_err());
+        assert!(Kdf::Argon2id {
+            memory: 0,
+            iterations: 1,
+            parallelism: 1
+        }
+        .derive(b"foobar", b"salt")
+        .is_err());
+    }
+
+    #[test]
+    fn excessive_parameters() {
+        assert!(Kdf::Argon2id {
+            memory: u32::MAX,
+            iterations: 1,
+            parallelism: 1
+        }
+        .derive(b"foobar", b"salt-salt-salt-1")
+        .is_err());
+        assert!(Kdf::Scrypt {
+            log_n: 40,
+            r: 8,
+            p: 1
+        }
+        .derive(b"foobar", b"salt-salt-salt-1")
+        .is_err());
+        assert!(Kdf::Scrypt {
+            log_n: 4,
+            r: u32::MAX,
+            p: 1
+        }
+        .derive(b"foobar", b"salt-salt-salt-1")
+        .is_err());
+        assert!(Kdf::Pbkdf2 { rounds: u32::MAX }
+            .derive(b"foobar", b"salt-salt-salt-1")
+            .is_err());
+    }
+
+    #[test]
+    fn parameters_round_trip() {
+        for kdf in CHEAP.iter().chain(std::iter::once(&Kdf::Legacy)) {
+            let mut bytes = Vec::new();
+            kdf.write(&mut bytes);
+            bytes.push(0xff);
+
+            let (read, rest) = Kdf::read(&bytes).unwrap();
+            assert_eq!(&read, kdf);
+            assert_eq!(rest, [0xff]);
+        }
+    }
+
+    #[test]
+    fn truncated_parameters() {
+        let mut bytes = Vec::new();
+        Kdf::default().write(&mut bytes);
+        for len in 0..bytes.len() {
+            assert!(Kdf::read(&bytes[..len]).is_err());
+        }
+    }
+}
@@ -1,6 +1,31 @@
 #![deny(warnings, rust_2018_idioms, missing_docs, clippy::pedantic)]
 
-//! Allows encrypting and decrypting serde payloads with AES/GCM encryption
+//! Allows encrypting and decrypting serde payloads with AES/GCM or XChaCha20-Poly1305 encryption
+
+mod cipher;
+mod compression;
+mod header;
+mod kdf;
+mod recipient;
+mod shamir;
+
+pub use header::{Cipher, Compression, Header, Serialization, VERSION};
+pub use kdf::Kdf;
+pub use kdf::SALT_LEN;
+pub use recipient::{Identity, Recipient, Stanza};
+pub use shamir::Share;
+
+/// Length of the authentication tag appended to the ciphertext
+const TAG_LEN: usize = 16;
+
+/// Length of a wrapped data key, including its authentication tag
+pub(crate) const WRAPPED_LEN: usize = kdf::KEY_LEN + TAG_LEN;
+
+/// Serialized payload before encryption or after decryption, wiped when dropped
+pub(crate) type Plaintext = zeroize::Zeroizing<Vec<u8>>;
+
+/// Key wiped when dropped
+pub(crate) type Key = zeroize::Zeroizing<[u8; kdf::KEY_LEN]>;
 
 /// Errors that can happen while serialization/deserialization, inflation/compression, and
 /// decryption/encryption
@@ -16,30 +41,367 @@ pub enum Error {
 
     /// Failed to encrypt/decrypt payload
     #[error("Failed to encrypt/decrypt payload: {0}")]
-    Crypto(aes_gcm::Error),
+    Crypto(aes_gcm::aead::Error),
 
     /// Failed to inflate payload
     #[error("Failed to inflate payload")]
     Inflation,
+
+    /// Failed to derive the key from the passphrase
+    #[error("Failed to derive key from passphrase")]
+    KeyDerivation,
+
+    /// The payload is shorter than its header and authentication tag require
+    #[error("Payload is truncated")]
+    Truncated,
+
+    /// The payload header is inconsistent
+    #[error("Payload is malformed")]
+    Malformed,
+
+    /// The payload was written with an unknown format version
+    #[error("Unsupported format version: {0}")]
+    UnsupportedVersion(u8),
+
+    /// The payload header names an algorithm that is unknown or not compiled in
+    #[error("Unsupported {kind} algorithm: {id}")]
+    UnsupportedAlgorithm {
+        /// Which kind of algorithm was requested
+        kind: &'static str,
+        /// Identifier found in the header
+        id: u8,
+    },
+
+    /// The payload header has flags set that are not understood
+    #[error("Unsupported header flags: {0:#06x}")]
+    UnsupportedFlags(u16),
+
+    /// An identity or recipient could not be parsed, or is not a valid key
+    #[error("Invalid identity or recipient")]
+    InvalidKey,
+
+    /// The payload is encrypted to recipients, but there is no identity to decrypt it with
+    #[error("Payload is encrypted to recipients, an identity is needed to decrypt it")]
+    NoIdentity,
+
+    /// The identity is not one of the recipients of the payload
+    #[error("Payload is not encrypted to this identity")]
+    NotRecipient,
+
+    /// An identity has no recipients left to encrypt to
+    #[error("No recipients to encrypt to")]
+    NoRecipients,
+
+    /// Shares could not be parsed, are of different splits, or are too few
+    #[error("Invalid or too few shares")]
+    InvalidShares,
+}
+
+/// Struct holding the passphrase that can be used to encrypt and decrypt payloads
+///
+/// Payloads are encrypted with a random data key, which is wrapped with a key derived from the
+/// passphrase and stored in the payload [`Header`](struct.Header.html). The data key is kept for
+/// the lifetime of the crypter, taken from the first payload it decrypts, so that it can be split
+/// into shares with [`split`](#method.split) and recovered with [`with_shares`](#method.with_shares)
+///
+/// A fresh random salt is generated for every encryption and the key is derived with the
+/// configured [`Kdf`](enum.Kdf.html). Both the salt and the derivation parameters are stored in
+/// the header, so decryption always uses whatever was used to encrypt
+///
+/// With [`with_key_reuse`](#method.with_key_reuse), derived keys are kept instead, trading the
+/// fresh salt for a single derivation per session
+///
+/// Instead of a passphrase, the key may be derived from a key file with
+/// [`from_key`](#method.from_key), or from both with [`with_key_file`](#method.with_key_file)
+///
+/// With [`with_recipients`](#method.with_recipients), payloads are encrypted to a list of public
+/// keys instead, and decrypted with the [`Identity`](struct.Identity.html) of any of them
+///
+/// The [`Cipher`](enum.Cipher.html) is kept from the first payload it decrypts, unless set with
+/// [`with_cipher`](#method.with_cipher), so that saving back does not change it
+///
+/// The passphrase, keys, and plaintext buffers are wiped from memory when dropped
+pub struct Crypter {
+    passphrase: zeroize::Zeroizing<Vec<u8>>,
+    kdf: Kdf,
+    compression: Compression,
+    cipher: std::sync::Mutex<Option<Cipher>>,
+    keys: Option<std::sync::Mutex<Vec<DerivedKey>>>,
+    identity: Option<Identity>,
+    recipients: std::sync::Mutex<Option<Vec<Recipient>>>,
+    data_key: std::sync::Mutex<Option<Key>>,
+    /// Whether the data key was recovered from shares, and so is used rather than unwrapped
+    recovered: bool,
 }
 
-/// Struct holding the cipher that can be used to encrypt and decrypt payloads
-pub struct Crypter(aes_gcm::Aes256Gcm);
+/// A key kept along with what it was derived with
+struct DerivedKey {
+    kdf: Kdf,
+    salt: [u8; SALT_LEN],
+    key: Key,
+}
 
 impl Crypter {
-    /// Creates a new cipher with the given passphrase
+    /// Creates a new cipher with the given passphrase, the default [`Kdf`](enum.Kdf.html), and
+    /// the default [`Compression`](enum.Compression.html)
     pub fn new<S: AsRef<str>>(passphrase: S) -> Self {
-        use aes_gcm::aead::generic_array::GenericArray;
-        use aes_gcm::aead::NewAead;
+        Self {
+            passphrase: zeroize::Zeroizing::new(Vec::from(passphrase.as_ref().as_bytes())),
+            kdf: Kdf::default(),
+            compression: Compression::default(),
+            cipher: std::sync::Mutex::new(None),
+            keys: None,
+            identity: None,
+            recipients: std::sync::Mutex::new(None),
+            data_key: std::sync::Mutex::new(None),
+            recovered: false,
+        }
+    }
+
+    /// Creates a new cipher that decrypts payloads encrypted to `identity`
+    ///
+    /// Encrypts to the recipients of the first payload it decrypts, so that saving back keeps
+    /// them, or to the identity alone before that
+    #[must_use]
+    pub fn from_identity(identity: Identity) -> Self {
+        Self {
+            identity: Some(identity),
+            ..Self::new("")
+        }
+    }
+
+    /// Creates a new cipher from raw key material, such as the contents of a key file, instead of
+    /// a passphrase
+    ///
+    /// The material is hashed first, so it may be of any length, and then goes through the
+    /// [`Kdf`](enum.Kdf.html) like a passphrase would
+    #[must_use]
+    pub fn from_key(key: &[u8]) -> Self {
+        use sha2::Digest;
+
+        Self {
+            passphrase: sha2::Sha256::digest(key).to_vec().into(),
+            ..Self::new("")
+        }
+    }
+
+    /// Requires the key material as well as the passphrase, like a key file in `KeePass`
+    ///
+    /// The hashes of both are hashed together, so neither is enough without the other
+    #[must_use]
+    pub fn with_key_file(mut self, key: &[u8]) -> Self {
         use sha2::Digest;
 
-        let secret = {
-            let mut hasher = sha2::Sha256::new();
-            hasher.update(passphrase.as_ref().as_bytes());
-            hasher.finalize()
+        let mut composite = sha2::Sha256::new();
+        composite.update(sha2::Sha256::digest(&self.passphrase));
+        composite.update(sha2::Sha256::digest(key));
+        self.passphrase = composite.finalize().to_vec().into();
+        self
+    }
+
+    /// Encrypts to `recipients` instead of with the passphrase or identity
+    ///
+    /// An empty list goes back to encrypting with the passphrase, and is an error for a cipher
+    /// created from an identity
+    #[must_use]
+    pub fn with_recipients(mut self, recipients: Vec<Recipient>) -> Self {
+        self.recipients = std::sync::Mutex::new(Some(recipients));
+        self
+    }
+
+    /// Recipients the next payload will be encrypted to, if any
+    pub fn recipients(&self) -> Vec<Recipient> {
+        let recipients = self
+            .recipients
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        match (&*recipients, &self.identity) {
+            (Some(recipients), _) => recipients.clone(),
+            (None, Some(identity)) => vec![identity.recipient()],
+            (None, None) => Vec::new(),
+        }
+    }
+
+    /// Splits the data key into `shares` shares, any `threshold` of which recover it
+    ///
+    /// Before anything is decrypted or encrypted, this picks the data key, so the shares only
+    /// apply to payloads encrypted from then on
+    ///
+    /// # Errors
+    /// * Threshold of zero or above the number of shares:
+    ///   [`InvalidShares`](enum.Error.html#variant.InvalidShares)
+    pub fn split(&self, shares: u8, threshold: u8) -> Result<Vec<Share>, Error> {
+        shamir::split(&self.data_key(), shares, threshold)
+    }
+
+    /// Decrypts with the data key recovered from `shares`, instead of unwrapping it
+    ///
+    /// The data key is kept for encryption, wrapped with the passphrase, so the shares still apply
+    ///
+    /// # Errors
+    /// * Shares of different splits or too few of them:
+    ///   [`InvalidShares`](enum.Error.html#variant.InvalidShares)
+    pub fn with_shares(mut self, shares: &[Share]) -> Result<Self, Error> {
+        self.data_key = std::sync::Mutex::new(Some(shamir::combine(shares)?));
+        self.recovered = true;
+        Ok(self)
+    }
+
+    /// Encrypts with a new data key from now on, so that whoever knew the previous one cannot
+    /// decrypt what is encrypted next
+    #[must_use]
+    pub fn with_new_data_key(mut self) -> Self {
+        self.data_key = std::sync::Mutex::new(None);
+        self.data_key();
+        self.recovered = false;
+        self
+    }
+
+    /// The data key, picked at random if there is none yet
+    fn data_key(&self) -> Key {
+        use rand::RngCore;
+
+        self.data_key
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .get_or_insert_with(|| {
+                let mut data_key = Key::default();
+                rand::thread_rng().fill_bytes(&mut *data_key);
+                data_key
+            })
+            .clone()
+    }
+
+    /// Sets the key derivation function used when encrypting
+    ///
+    /// Decryption ignores this setting and uses the function recorded in the payload
+    #[must_use]
+    pub fn with_kdf(mut self, kdf: Kdf) -> Self {
+        self.kdf = kdf;
+        self
+    }
+
+    /// Sets the compression applied before encrypting
+    ///
+    /// Decryption ignores this setting and uses the compression recorded in the payload
+    #[must_use]
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets the cipher used when encrypting
+    ///
+    /// Decryption ignores this setting and uses the cipher recorded in the payload
+    #[must_use]
+    pub fn with_cipher(mut self, cipher: Cipher) -> Self {
+        self.cipher = std::sync::Mutex::new(Some(cipher));
+        self
+    }
+
+    /// Cipher the next payload will be encrypted with
+    pub fn cipher(&self) -> Cipher {
+        self.cipher
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .unwrap_or_default()
+    }
+
+    /// Keeps every derived key for the lifetime of the crypter
+    ///
+    /// Decrypting derives each distinct salt only once, and encrypting reuses the salt and
+    /// derivation of the first salted key, so that a payload can be loaded and saved back with a
+    /// single derivation. Payloads encrypted this way share their key and differ only by nonce
+    #[must_use]
+    pub fn with_key_reuse(mut self) -> Self {
+        self.keys = Some(std::sync::Mutex::new(Vec::new()));
+        self
+    }
+
+    /// Salt and derivation for the next encryption
+    fn next_salt(&self) -> (Kdf, [u8; SALT_LEN]) {
+        use rand::RngCore;
+
+        if let Some(keys) = &self.keys {
+            let keys = keys
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+            if let Some(reused) = keys.iter().find(|key| key.kdf != Kdf::Legacy) {
+                return (reused.kdf, reused.salt);
+            }
+        }
+
+        let mut salt = [0; SALT_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        (self.kdf, salt)
+    }
+
+    fn derived_key(&self, kdf: Kdf, salt: &[u8; SALT_LEN]) -> Result<Key, Error> {
+        let derive = || {
+            // The unsalted derivation predates salts altogether
+            if kdf == Kdf::Legacy {
+                kdf.derive(&self.passphrase, &[])
+            } else {
+                kdf.derive(&self.passphrase, salt)
+            }
         };
 
-        Self(aes_gcm::Aes256Gcm::new(GenericArray::from_slice(&secret)))
+        if let Some(keys) = &self.keys {
+            let mut keys = keys
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+            if let Some(known) = keys.iter().find(|key| key.kdf == kdf && &key.salt == salt) {
+                Ok(known.key.clone())
+            } else {
+                let key = derive()?;
+                keys.push(DerivedKey {
+                    kdf,
+                    salt: *salt,
+                    key: key.clone(),
+                });
+                Ok(key)
+            }
+        } else {
+            derive()
+        }
+    }
+
+    /// The key the payload is encrypted with, and whether it is a data key rather than the key
+    /// derived from the passphrase, as in payloads from before data keys
+    fn payload_key(&self, header: &Header) -> Result<(Key, bool), Error> {
+        if self.recovered && (header.wrapped_key.is_some() || !header.recipients.is_empty()) {
+            return Ok((self.data_key(), true));
+        }
+
+        match (&self.identity, header.recipients.is_empty()) {
+            (None, true) => {
+                let key = self.derived_key(header.kdf, &header.salt)?;
+                let Some(wrapped_key) = &header.wrapped_key else {
+                    return Ok((key, false));
+                };
+
+                let opened =
+                    header
+                        .cipher
+                        .decrypt(&key[..], &header.nonce, &wrapped_key[..], &[])?;
+                let mut data_key = Key::default();
+                data_key.copy_from_slice(&opened);
+                Ok((data_key, true))
+            }
+            (None, false) => Err(Error::NoIdentity),
+            (Some(_), true) => Err(Error::NotRecipient),
+            (Some(identity), false) => {
+                let recipient = identity.recipient();
+                let data_key = header
+                    .recipients
+                    .iter()
+                    .find(|stanza| stanza.recipient == recipient)
+                    .ok_or(Error::NotRecipient)?
+                    .unwrap(identity)?;
+                Ok((data_key, true))
+            }
+        }
     }
 
     /// Encrypts the payload
@@ -47,69 +409,180 @@ impl Crypter {
     /// # Errors
     /// Can fail at any of these points:
     /// * Serialization: [`Serde`](enum.Error.html#variant.Serde)
+    /// * Key derivation: [`KeyDerivation`](enum.Error.html#variant.KeyDerivation)
+    /// * Key wrapping: [`InvalidKey`](enum.Error.html#variant.InvalidKey) or
+    ///   [`NoRecipients`](enum.Error.html#variant.NoRecipients)
     /// * Encryption: [`Crypto`](enum.Error.html#variant.Crypto)
     pub fn encrypt<T: serde::Serialize>(&self, payload: &T) -> Result<Vec<u8>, Error> {
-        use aes_gcm::aead::Aead;
+        use rand::RngCore;
+
+        let cipher = self.cipher();
+        let (kdf, salt) = self.next_salt();
+        let data_key = self.data_key();
 
-        let mut binary = Vec::new();
+        let mut binary = Plaintext::default();
         payload
-            .serialize(&mut rmp_serde::Serializer::new(&mut binary))
+            .serialize(&mut rmp_serde::Serializer::new(&mut *binary))
             .map_err(Error::Serialize)?;
 
-        #[cfg(feature = "miniz_oxide")]
-        let binary = miniz_oxide::deflate::compress_to_vec(&binary, 8);
-
-        let nonce = {
-            use rand::RngCore;
+        let binary = self.compression.compress(binary);
 
-            let mut bytes = [0_u8; 12];
-            rand::thread_rng().fill_bytes(&mut bytes);
-            aes_gcm::aead::generic_array::GenericArray::from(bytes)
+        let mut header = Header {
+            version: VERSION,
+            cipher,
+            kdf,
+            compression: self.compression,
+            serialization: Serialization::MessagePack,
+            flags: 0,
+            salt,
+            nonce: vec![0; cipher.nonce_len()],
+            wrapped_key: None,
+            recipients: Vec::new(),
         };
+        rand::thread_rng().fill_bytes(&mut header.nonce);
+
+        let recipients = self.recipients();
+        if recipients.is_empty() {
+            if self.identity.is_some() {
+                return Err(Error::NoRecipients);
+            }
+
+            // The derived key is not the data key, so both can use the nonce of the payload
+            let sealed = cipher.encrypt(
+                &self.derived_key(kdf, &salt)?[..],
+                &header.nonce,
+                &data_key[..],
+                &[],
+            )?;
+            let mut wrapped_key = [0; WRAPPED_LEN];
+            wrapped_key.copy_from_slice(&sealed);
+            header.flags = header::WRAPPED_KEY;
+            header.wrapped_key = Some(wrapped_key);
+        } else {
+            header.flags = header::RECIPIENTS;
+            header.recipients = recipients
+                .into_iter()
+                .map(|recipient| Stanza::wrap(&data_key, recipient))
+                .collect::<Result<Vec<_>, _>>()?;
+        }
+
+        let mut output = Vec::new();
+        header.write(&mut output);
 
-        let data = self
-            .0
-            .encrypt(&nonce, binary.as_slice())
-            .map_err(Error::Crypto)?;
+        let data = cipher.encrypt(&data_key[..], &header.nonce, &binary, &output)?;
 
-        Ok(nonce.into_iter().chain(data.into_iter()).collect())
+        output.extend(data);
+        Ok(output)
     }
 
     /// Decrypts into the payload
     ///
     /// # Errors
     /// Can fail at any of these points:
+    /// * Header parsing: any of the errors from [`Header::parse`](struct.Header.html#method.parse)
+    /// * Missing authentication tag: [`Truncated`](enum.Error.html#variant.Truncated)
+    /// * Key derivation: [`KeyDerivation`](enum.Error.html#variant.KeyDerivation)
+    /// * Key unwrapping: [`NoIdentity`](enum.Error.html#variant.NoIdentity) or
+    ///   [`NotRecipient`](enum.Error.html#variant.NotRecipient)
     /// * Deserialization: [`Serde`](enum.Error.html#variant.Serde)
     /// * Decryption: [`Crypto`](enum.Error.html#variant.Crypto)
     /// * Inflation: [`Inflation`](enum.Error.html#variant.Inflation)
-    ///   * Only if feature `compression` is enabled
     pub fn decrypt<T: serde::de::DeserializeOwned>(&self, payload: &[u8]) -> Result<T, Error> {
-        use aes_gcm::aead::Aead;
-
-        let (nonce, payload) = {
-            let mut bytes = [0_u8; 12];
-            bytes.copy_from_slice(&payload[..12]);
-            (
-                aes_gcm::aead::generic_array::GenericArray::from(bytes),
-                &payload[12..],
-            )
-        };
+        let (header, associated_data, payload) = Header::split(payload)?;
+        if payload.len() < TAG_LEN {
+            return Err(Error::Truncated);
+        }
+
+        let (key, is_data_key) = self.payload_key(&header)?;
+        let decrypted = header
+            .cipher
+            .decrypt(&key[..], &header.nonce, payload, associated_data)?;
 
-        let decrypted: Vec<u8> = self.0.decrypt(&nonce, payload).map_err(Error::Crypto)?;
+        // The cipher, data key, and recipients are only kept once the header is authenticated
+        self.cipher
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .get_or_insert(header.cipher);
+        if is_data_key {
+            self.data_key
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .get_or_insert(key);
+        }
+        if !header.recipients.is_empty() {
+            let mut recipients = self
+                .recipients
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+            if recipients.is_none() {
+                *recipients = Some(
+                    header
+                        .recipients
+                        .iter()
+                        .map(|stanza| stanza.recipient)
+                        .collect(),
+                );
+            }
+        }
 
-        // Allowed because the returned error is quite useless, just a number
-        #[allow(clippy::map_err_ignore)]
-        #[cfg(feature = "miniz_oxide")]
-        let decrypted =
-            miniz_oxide::inflate::decompress_to_vec(&decrypted).map_err(|_| Error::Inflation)?;
+        // Legacy payloads did not record whether they were compressed, so try both
+        if header.version == 0 {
+            return rmp_serde::from_slice(&decrypted).or_else(|error| {
+                Compression::Deflate
+                    .decompress(decrypted)
+                    .ok()
+                    .and_then(|inflated| rmp_serde::from_slice(&inflated).ok())
+                    .ok_or(Error::Deserialize(error))
+            });
+        }
 
-        rmp_serde::from_read_ref(&decrypted).map_err(Error::Deserialize)
+        let decrypted = header.compression.decompress(decrypted)?;
+
+        match header.serialization {
+            Serialization::MessagePack => {
+                rmp_serde::from_slice(&decrypted).map_err(Error::Deserialize)
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::Cipher;
+    use super::Compression;
     use super::Crypter;
+    use super::Kdf;
+
+    const CIPHERS: [Cipher; 2] = [Cipher::Aes256Gcm, Cipher::XChaCha20Poly1305];
+
+    const CHEAP: Kdf = Kdf::Argon2id {
+        memory: 64,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    fn legacy_encrypt<T: serde::Serialize>(
+        passphrase: &str,
+        payload: &T,
+        deflate: bool,
+    ) -> Vec<u8> {
+        use aes_gcm::aead::generic_array::GenericArray;
+        use aes_gcm::aead::{Aead, KeyInit};
+        use sha2::Digest;
+
+        let key = sha2::Sha256::digest(passphrase.as_bytes());
+        let cipher = aes_gcm::Aes256Gcm::new(GenericArray::from_slice(&key));
+        let nonce = GenericArray::from([7_u8; 12]);
+        let mut binary = rmp_serde::to_vec(payload).unwrap();
+        if deflate {
+            binary = miniz_oxide::deflate::compress_to_vec(&binary, 8);
+        }
+
+        nonce
+            .into_iter()
+            .chain(cipher.encrypt(&nonce, binary.as_slice()).unwrap())
+            .collect()
+    }
 
     #[test]
     fn can_contruct() {
@@ -127,12 +600,447 @@ mod tests {
         map.insert(String::from("foo"), 123_i32);
         map.insert(String::from("bar"), 321_i32);
 
-        let crypter = Crypter::new("foo\u{1f1e7}\u{1f1f7}\u{1f1f3}\u{1f1f4}bar");
-        let encrypted = crypter.encrypt(&map).unwrap();
-        let decrypted = crypter
-            .decrypt::<std::collections::HashMap<String, i32>>(&encrypted)
+        for cipher in &CIPHERS {
+            let crypter =
+                Crypter::new("foo\u{1f1e7}\u{1f1f7}\u{1f1f3}\u{1f1f4}bar").with_cipher(*cipher);
+            let encrypted = crypter.encrypt(&map).unwrap();
+            let decrypted = crypter
+                .decrypt::<std::collections::HashMap<String, i32>>(&encrypted)
+                .unwrap();
+
+            assert_eq!(map, decrypted);
+        }
+    }
+
+    #[test]
+    fn ciphers() {
+        for cipher in &CIPHERS {
+            let encrypted = Crypter::new("foobar")
+                .with_kdf(CHEAP)
+                .with_cipher(*cipher)
+                .encrypt(&"payload")
+                .unwrap();
+            let header = super::Header::parse(&encrypted).unwrap();
+            assert_eq!(header.cipher, *cipher);
+            assert_eq!(header.nonce.len(), cipher.nonce_len());
+
+            // Decryption reads the cipher from the payload, and saving back keeps it
+            let loaded = Crypter::new("foobar");
+            assert_eq!(loaded.decrypt::<String>(&encrypted).unwrap(), "payload");
+            assert_eq!(loaded.cipher(), *cipher);
+            let saved = loaded.encrypt(&"payload").unwrap();
+            assert_eq!(super::Header::parse(&saved).unwrap().cipher, *cipher);
+        }
+
+        // Unless another one is asked for
+        let encrypted = Crypter::new("foobar")
+            .with_kdf(CHEAP)
+            .with_cipher(Cipher::XChaCha20Poly1305)
+            .encrypt(&"payload")
+            .unwrap();
+        let loaded = Crypter::new("foobar").with_cipher(Cipher::Aes256Gcm);
+        loaded.decrypt::<String>(&encrypted).unwrap();
+        assert_eq!(loaded.cipher(), Cipher::Aes256Gcm);
+    }
+
+    #[test]
+    fn round_trip_all_kdfs() {
+        let kdfs = [
+            CHEAP,
+            Kdf::Scrypt {
+                log_n: 4,
+                r: 8,
+                p: 1,
+            },
+            Kdf::Pbkdf2 { rounds: 16 },
+        ];
+
+        for (kdf, cipher) in kdfs.iter().zip(CIPHERS.iter().cycle()) {
+            let crypter = Crypter::new("foobar").with_kdf(*kdf).with_cipher(*cipher);
+            let encrypted = crypter.encrypt(&"payload").unwrap();
+
+            // Decryption reads the parameters from the payload
+            let decrypted = Crypter::new("foobar")
+                .decrypt::<String>(&encrypted)
+                .unwrap();
+            assert_eq!(decrypted, "payload");
+        }
+    }
+
+    #[test]
+    fn salted() {
+        let crypter = Crypter::new("foobar").with_kdf(CHEAP);
+        let first = crypter.encrypt(&"payload").unwrap();
+        let second = crypter.encrypt(&"payload").unwrap();
+
+        let salt = |bytes: &[u8]| super::Header::parse(bytes).unwrap().salt;
+        assert_ne!(salt(&first), salt(&second));
+    }
+
+    #[test]
+    fn key_reuse() {
+        let crypter = Crypter::new("foobar")
+            .with_kdf(Kdf::Pbkdf2 { rounds: 16 })
+            .with_key_reuse();
+        let loaded = Crypter::new("foobar")
+            .with_kdf(CHEAP)
+            .encrypt(&"payload")
+            .unwrap();
+        assert_eq!(crypter.decrypt::<String>(&loaded).unwrap(), "payload");
+
+        // Saving back keeps the derivation and salt of the loaded payload
+        let saved = crypter.encrypt(&"payload").unwrap();
+        let header = |bytes: &[u8]| super::Header::parse(bytes).unwrap();
+        assert_eq!(header(&saved).kdf, CHEAP);
+        assert_eq!(header(&saved).salt, header(&loaded).salt);
+        assert_ne!(header(&saved).nonce, header(&loaded).nonce);
+        assert_eq!(
+            Crypter::new("foobar").decrypt::<String>(&saved).unwrap(),
+            "payload"
+        );
+
+        // Legacy keys are never reused for encryption
+        let crypter = Crypter::new("foobar").with_kdf(CHEAP).with_key_reuse();
+        let legacy = legacy_encrypt("foobar", &"payload", false);
+        assert_eq!(crypter.decrypt::<String>(&legacy).unwrap(), "payload");
+        let saved = crypter.encrypt(&"payload").unwrap();
+        assert_eq!(header(&saved).kdf, CHEAP);
+    }
+
+    #[test]
+    fn wrong_passphrase() {
+        for cipher in &CIPHERS {
+            let encrypted = Crypter::new("foobar")
+                .with_kdf(CHEAP)
+                .with_cipher(*cipher)
+                .encrypt(&"payload")
+                .unwrap();
+            assert!(Crypter::new("barfoo")
+                .decrypt::<String>(&encrypted)
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn key_file() {
+        let key = [0xab_u8; 64];
+
+        let encrypted = Crypter::from_key(&key)
+            .with_kdf(CHEAP)
+            .encrypt(&"payload")
+            .unwrap();
+        assert_eq!(
+            Crypter::from_key(&key)
+                .decrypt::<String>(&encrypted)
+                .unwrap(),
+            "payload"
+        );
+        assert!(Crypter::from_key(&key[1..])
+            .decrypt::<String>(&encrypted)
+            .is_err());
+        assert!(Crypter::new("").decrypt::<String>(&encrypted).is_err());
+    }
+
+    #[test]
+    fn passphrase_and_key_file() {
+        let key = b"key file contents";
+
+        let encrypted = Crypter::new("foobar")
+            .with_key_file(key)
+            .with_kdf(CHEAP)
+            .encrypt(&"payload")
+            .unwrap();
+        assert_eq!(
+            Crypter::new("foobar")
+                .with_key_file(key)
+                .decrypt::<String>(&encrypted)
+                .unwrap(),
+            "payload"
+        );
+
+        assert!(Crypter::new("foobar")
+            .decrypt::<String>(&encrypted)
+            .is_err());
+        assert!(Crypter::from_key(key)
+            .decrypt::<String>(&encrypted)
+            .is_err());
+        assert!(Crypter::new("barfoo")
+            .with_key_file(key)
+            .decrypt::<String>(&encrypted)
+            .is_err());
+    }
+
+    #[test]
+    fn recipients() {
+        let alice = super::Identity::generate();
+        let bob = super::Identity::generate();
+
+        let encrypted = Crypter::new("")
+            .with_recipients(vec![alice.recipient(), bob.recipient()])
+            .encrypt(&"payload")
+            .unwrap();
+        assert_eq!(
+            super::Header::parse(&encrypted).unwrap().recipients.len(),
+            2
+        );
+
+        for identity in &[&alice, &bob] {
+            let crypter = Crypter::from_identity((*identity).clone());
+            assert_eq!(crypter.decrypt::<String>(&encrypted).unwrap(), "payload");
+            // Saving back keeps every recipient
+            assert_eq!(
+                crypter.recipients(),
+                vec![alice.recipient(), bob.recipient()]
+            );
+        }
+
+        assert!(matches!(
+            Crypter::from_identity(super::Identity::generate()).decrypt::<String>(&encrypted),
+            Err(super::Error::NotRecipient)
+        ));
+        assert!(matches!(
+            Crypter::new("").decrypt::<String>(&encrypted),
+            Err(super::Error::NoIdentity)
+        ));
+    }
+
+    #[test]
+    fn identity() {
+        let identity = super::Identity::generate();
+        let crypter = Crypter::from_identity(identity.clone());
+        assert_eq!(crypter.recipients(), vec![identity.recipient()]);
+
+        let encrypted = crypter.encrypt(&"payload").unwrap();
+        assert_eq!(
+            Crypter::from_identity(identity.clone())
+                .decrypt::<String>(&encrypted)
+                .unwrap(),
+            "payload"
+        );
+
+        let passphrase = Crypter::new("")
+            .with_kdf(CHEAP)
+            .encrypt(&"payload")
+            .unwrap();
+        assert!(matches!(
+            crypter.decrypt::<String>(&passphrase),
+            Err(super::Error::NotRecipient)
+        ));
+
+        assert!(matches!(
+            Crypter::from_identity(identity)
+                .with_recipients(Vec::new())
+                .encrypt(&"payload"),
+            Err(super::Error::NoRecipients)
+        ));
+    }
+
+    #[test]
+    fn recipients_are_authenticated() {
+        let identity = super::Identity::generate();
+        let mut encrypted = Crypter::new("")
+            .with_recipients(vec![identity.recipient()])
+            .encrypt(&"payload")
+            .unwrap();
+
+        // Swapping the wrapped key for one of another payload is caught by the AEAD
+        let other = Crypter::new("")
+            .with_recipients(vec![identity.recipient()])
+            .encrypt(&"payload")
             .unwrap();
+        let ephemeral = super::Header::parse(&encrypted).unwrap().recipients[0].ephemeral;
+        let start = encrypted
+            .windows(ephemeral.len())
+            .position(|window| window == ephemeral)
+            .unwrap()
+            - ephemeral.len();
+        let stanza = start..start + super::Stanza::LEN;
+        encrypted[stanza.clone()].copy_from_slice(&other[stanza]);
+        assert_eq!(
+            super::Header::parse(&encrypted).unwrap().recipients,
+            super::Header::parse(&other).unwrap().recipients
+        );
+
+        assert!(Crypter::from_identity(identity)
+            .decrypt::<String>(&encrypted)
+            .is_err());
+    }
+
+    #[test]
+    fn data_key() {
+        let crypter = Crypter::new("foobar").with_kdf(CHEAP);
+        let encrypted = crypter.encrypt(&"payload").unwrap();
+        assert!(super::Header::parse(&encrypted)
+            .unwrap()
+            .wrapped_key
+            .is_some());
+
+        // Loading and saving back keeps the data key, whatever the passphrase is
+        let loaded = Crypter::new("foobar");
+        loaded.decrypt::<String>(&encrypted).unwrap();
+        assert_eq!(loaded.data_key(), crypter.data_key());
+
+        let renewed = Crypter::new("foobar").with_new_data_key();
+        renewed.decrypt::<String>(&encrypted).unwrap();
+        assert_ne!(renewed.data_key(), crypter.data_key());
+    }
+
+    #[test]
+    fn without_data_key() {
+        // Payloads from before data keys are encrypted with the derived key directly
+        let crypter = Crypter::new("foobar");
+        let header = super::Header {
+            version: super::VERSION,
+            cipher: Cipher::Aes256Gcm,
+            kdf: CHEAP,
+            compression: Compression::None,
+            serialization: super::Serialization::MessagePack,
+            flags: 0,
+            salt: [1; super::SALT_LEN],
+            nonce: vec![2; super::header::NONCE_LEN],
+            wrapped_key: None,
+            recipients: Vec::new(),
+        };
+        let mut encrypted = Vec::new();
+        header.write(&mut encrypted);
+        let key = crypter.derived_key(CHEAP, &header.salt).unwrap();
+        let data = header
+            .cipher
+            .encrypt(
+                &key[..],
+                &header.nonce,
+                &rmp_serde::to_vec(&"payload").unwrap(),
+                &encrypted,
+            )
+            .unwrap();
+        encrypted.extend(data);
+
+        assert_eq!(crypter.decrypt::<String>(&encrypted).unwrap(), "payload");
+        // The derived key is not a data key to keep
+        assert_ne!(crypter.data_key(), key);
 
-        assert_eq!(map, decrypted);
+        // And a recovered data key cannot open them
+        let shares = Crypter::new("").split(1, 1).unwrap();
+        assert!(Crypter::new("barfoo")
+            .with_shares(&shares)
+            .unwrap()
+            .decrypt::<String>(&encrypted)
+            .is_err());
+    }
+
+    #[test]
+    fn shares() {
+        let identity = super::Identity::generate();
+        let crypter = Crypter::from_identity(identity.clone());
+        let encrypted = crypter.encrypt(&"payload").unwrap();
+        let shares = crypter.split(5, 3).unwrap();
+        assert_eq!(shares.len(), 5);
+
+        // Without the identity, three shares are enough to decrypt and save with a new passphrase
+        let recovered = Crypter::new("barfoo")
+            .with_kdf(CHEAP)
+            .with_shares(&shares[1..4])
+            .unwrap()
+            .with_recipients(Vec::new());
+        assert_eq!(recovered.decrypt::<String>(&encrypted).unwrap(), "payload");
+        let saved = recovered.encrypt(&"payload").unwrap();
+        assert_eq!(
+            Crypter::new("barfoo").decrypt::<String>(&saved).unwrap(),
+            "payload"
+        );
+
+        // The shares still apply to what was saved
+        let again = Crypter::new("").with_shares(&shares[..3]).unwrap();
+        assert_eq!(again.decrypt::<String>(&saved).unwrap(), "payload");
+
+        assert!(matches!(
+            Crypter::new("").with_shares(&shares[..2]),
+            Err(super::Error::InvalidShares)
+        ));
+        let other = Crypter::new("").split(5, 3).unwrap();
+        assert!(Crypter::new("")
+            .with_shares(&other[..3])
+            .unwrap()
+            .decrypt::<String>(&encrypted)
+            .is_err());
+    }
+
+    #[test]
+    fn header_is_authenticated() {
+        for cipher in &CIPHERS {
+            let crypter = Crypter::new("foobar").with_kdf(CHEAP).with_cipher(*cipher);
+            let mut encrypted = crypter.encrypt(&"payload").unwrap();
+
+            // Flip the serialization identifier to another (unknown) one and back to make sure
+            // the header is actually read, then tamper with the salt, which is only caught by the
+            // AEAD
+            encrypted[7] = 200;
+            assert!(matches!(
+                crypter.decrypt::<String>(&encrypted),
+                Err(super::Error::UnsupportedAlgorithm { id: 200, .. })
+            ));
+            encrypted[7] = 0;
+            assert!(crypter.decrypt::<String>(&encrypted).is_ok());
+
+            let salt = super::Header::parse(&encrypted).unwrap().salt;
+            let salt_index = encrypted
+                .windows(salt.len())
+                .position(|window| window == salt)
+                .unwrap();
+            encrypted[salt_index] ^= 1;
+            assert!(matches!(
+                crypter.decrypt::<String>(&encrypted),
+                Err(super::Error::Crypto(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        for cipher in &CIPHERS {
+            let crypter = Crypter::new("foobar").with_kdf(CHEAP).with_cipher(*cipher);
+            let encrypted = crypter.encrypt(&"payload").unwrap();
+
+            for len in 0..encrypted.len() {
+                assert!(crypter.decrypt::<String>(&encrypted[..len]).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_all_compressions() {
+        let compressions = [Compression::None, Compression::Deflate, Compression::Zstd];
+        let payload = vec![String::from("payload"); 32];
+
+        for (compression, cipher) in compressions.iter().zip(CIPHERS.iter().cycle()) {
+            let encrypted = Crypter::new("foobar")
+                .with_kdf(CHEAP)
+                .with_cipher(*cipher)
+                .with_compression(*compression)
+                .encrypt(&payload)
+                .unwrap();
+            assert_eq!(
+                super::Header::parse(&encrypted).unwrap().compression,
+                *compression
+            );
+
+            // Decryption reads the compression from the payload
+            let decrypted = Crypter::new("foobar")
+                .with_compression(Compression::None)
+                .decrypt::<Vec<String>>(&encrypted)
+                .unwrap();
+            assert_eq!(decrypted, payload);
+        }
+    }
+
+    #[test]
+    fn legacy() {
+        for deflate in &[false, true] {
+            let encrypted = legacy_encrypt("foobar", &"payload", *deflate);
+            let decrypted = Crypter::new("foobar")
+                .decrypt::<String>(&encrypted)
+                .unwrap();
+            assert_eq!(decrypted, "payload");
+        }
     }
 }
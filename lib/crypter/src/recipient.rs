@@ -0,0 +1,274 @@
+//! Public-key encryption to a list of recipients, in the style of `age`
+//!
+//! Every payload is encrypted with a random data key, which is then wrapped for each
+//! recipient. Wrapping exchanges an ephemeral X25519 key with the recipient, derives the wrapping
+//! key from the shared secret with HKDF-SHA256, salted with both public keys, and encrypts the
+//! data key with AES-256-GCM. The wrapping key is never reused, so the nonce is always zero
+
+use super::Error;
+use super::WRAPPED_LEN;
+
+/// Length of X25519 keys
+pub(crate) const KEY_LEN: usize = 32;
+
+const IDENTITY_PREFIX: &str = "psfr-sk-";
+const RECIPIENT_PREFIX: &str = "psfr-pk-";
+
+/// Binds wrapping keys to this use
+const INFO: &[u8] = b"passifier x25519";
+
+/// Secret key that payloads are encrypted to, through its [`Recipient`](struct.Recipient.html)
+///
+/// Written as `psfr-sk-` followed by the key in hex. Parsing skips blank lines and lines starting
+/// with `#`, so an identity file may carry comments
+#[derive(Clone)]
+pub struct Identity(x25519_dalek::StaticSecret);
+
+impl Identity {
+    /// Generates a new random identity
+    #[must_use]
+    pub fn generate() -> Self {
+        Self(x25519_dalek::StaticSecret::random_from_rng(
+            rand::rngs::OsRng,
+        ))
+    }
+
+    /// The public key of the identity
+    #[must_use]
+    pub fn recipient(&self) -> Recipient {
+        Recipient(x25519_dalek::PublicKey::from(&self.0).to_bytes())
+    }
+}
+
+// Never shows the secret key
+impl std::fmt::Debug for Identity {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.debug_tuple("Identity")
+            .field(&self.recipient())
+            .finish()
+    }
+}
+
+impl std::fmt::Display for Identity {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "{IDENTITY_PREFIX}{}", hex::encode(self.0.to_bytes()))
+    }
+}
+
+impl std::str::FromStr for Identity {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let mut lines = string
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+
+        match (lines.next(), lines.next()) {
+            (Some(line), None) => Ok(Self(x25519_dalek::StaticSecret::from(*parse_key(
+                line,
+                IDENTITY_PREFIX,
+            )?))),
+            _ => Err(Error::InvalidKey),
+        }
+    }
+}
+
+/// Public key that payloads can be encrypted to
+///
+/// Written as `psfr-pk-` followed by the key in hex
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Recipient([u8; KEY_LEN]);
+
+impl std::fmt::Display for Recipient {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "{RECIPIENT_PREFIX}{}", hex::encode(self.0))
+    }
+}
+
+impl std::str::FromStr for Recipient {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        parse_key(string.trim(), RECIPIENT_PREFIX).map(|key| Self(*key))
+    }
+}
+
+fn parse_key(string: &str, prefix: &str) -> Result<super::Key, Error> {
+    let mut key = super::Key::default();
+    hex::decode_to_slice(
+        string.strip_prefix(prefix).ok_or(Error::InvalidKey)?,
+        &mut *key,
+    )
+    .map_err(|_| Error::InvalidKey)?;
+    Ok(key)
+}
+
+/// The data key of a payload, wrapped for one recipient
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stanza {
+    /// Who the data key is wrapped for
+    pub recipient: Recipient,
+    /// Public half of the ephemeral key exchanged with the recipient
+    pub ephemeral: [u8; KEY_LEN],
+    /// The wrapped data key, followed by its authentication tag
+    pub wrapped: [u8; WRAPPED_LEN],
+}
+
+impl Stanza {
+    /// Length of a stanza in the header
+    pub(crate) const LEN: usize = 2 * KEY_LEN + WRAPPED_LEN;
+
+    /// Wraps `data_key` for `recipient`
+    pub(crate) fn wrap(data_key: &[u8; KEY_LEN], recipient: Recipient) -> Result<Self, Error> {
+        use aes_gcm::aead::Aead;
+
+        let secret = x25519_dalek::EphemeralSecret::random_from_rng(rand::rngs::OsRng);
+        let ephemeral = x25519_dalek::PublicKey::from(&secret).to_bytes();
+        let shared = secret.diffie_hellman(&x25519_dalek::PublicKey::from(recipient.0));
+        // Low order points would make the shared secret predictable
+        if !shared.was_contributory() {
+            return Err(Error::InvalidKey);
+        }
+
+        let sealed = wrapping_cipher(shared.as_bytes(), &ephemeral, &recipient)
+            .encrypt(
+                aes_gcm::aead::generic_array::GenericArray::from_slice(
+                    &[0; super::header::NONCE_LEN],
+                ),
+                &data_key[..],
+            )
+            .map_err(Error::Crypto)?;
+
+        let mut wrapped = [0; WRAPPED_LEN];
+        wrapped.copy_from_slice(&sealed);
+        Ok(Self {
+            recipient,
+            ephemeral,
+            wrapped,
+        })
+    }
+
+    /// Unwraps the data key with `identity`, which must be the recipient of the stanza
+    pub(crate) fn unwrap(&self, identity: &Identity) -> Result<super::Key, Error> {
+        use aes_gcm::aead::Aead;
+
+        let shared = identity
+            .0
+            .diffie_hellman(&x25519_dalek::PublicKey::from(self.ephemeral));
+        if !shared.was_contributory() {
+            return Err(Error::Malformed);
+        }
+
+        let opened = super::Plaintext::new(
+            wrapping_cipher(shared.as_bytes(), &self.ephemeral, &self.recipient)
+                .decrypt(
+                    aes_gcm::aead::generic_array::GenericArray::from_slice(
+                        &[0; super::header::NONCE_LEN],
+                    ),
+                    &self.wrapped[..],
+                )
+                .map_err(Error::Crypto)?,
+        );
+
+        let mut data_key = super::Key::default();
+        data_key.copy_from_slice(&opened);
+        Ok(data_key)
+    }
+
+    pub(crate) fn write(&self, output: &mut Vec<u8>) {
+        output.extend_from_slice(&self.recipient.0);
+        output.extend_from_slice(&self.ephemeral);
+        output.extend_from_slice(&self.wrapped);
+    }
+
+    pub(crate) fn read(body: &[u8]) -> Result<(Self, &[u8]), Error> {
+        if body.len() < Self::LEN {
+            return Err(Error::Truncated);
+        }
+
+        let mut stanza = Self {
+            recipient: Recipient([0; KEY_LEN]),
+            ephemeral: [0; KEY_LEN],
+            wrapped: [0; WRAPPED_LEN],
+        };
+        stanza.recipient.0.copy_from_slice(&body[..KEY_LEN]);
+        stanza
+            .ephemeral
+            .copy_from_slice(&body[KEY_LEN..2 * KEY_LEN]);
+        stanza
+            .wrapped
+            .copy_from_slice(&body[2 * KEY_LEN..Self::LEN]);
+        Ok((stanza, &body[Self::LEN..]))
+    }
+}
+
+fn wrapping_cipher(
+    shared: &[u8; KEY_LEN],
+    ephemeral: &[u8; KEY_LEN],
+    recipient: &Recipient,
+) -> aes_gcm::Aes256Gcm {
+    use aes_gcm::aead::KeyInit;
+
+    let mut salt = [0; 2 * KEY_LEN];
+    salt[..KEY_LEN].copy_from_slice(ephemeral);
+    salt[KEY_LEN..].copy_from_slice(&recipient.0);
+
+    let mut key = super::Key::default();
+    hkdf::Hkdf::<sha2::Sha256>::new(Some(&salt), shared)
+        .expand(INFO, &mut *key)
+        .expect("32 bytes is a valid length for HKDF-SHA256");
+    aes_gcm::Aes256Gcm::new(aes_gcm::aead::generic_array::GenericArray::from_slice(
+        &key[..],
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Identity, Recipient, Stanza};
+
+    #[test]
+    fn wrap() {
+        let identity = Identity::generate();
+        let data_key = [7; super::KEY_LEN];
+
+        let stanza = Stanza::wrap(&data_key, identity.recipient()).unwrap();
+        assert_eq!(*stanza.unwrap(&identity).unwrap(), data_key);
+        assert!(stanza.unwrap(&Identity::generate()).is_err());
+
+        let mut bytes = Vec::new();
+        stanza.write(&mut bytes);
+        assert_eq!(bytes.len(), Stanza::LEN);
+        assert_eq!(Stanza::read(&bytes).unwrap(), (stanza, &[][..]));
+    }
+
+    #[test]
+    fn low_order_recipient() {
+        let recipient = Recipient([0; super::KEY_LEN]);
+        assert!(Stanza::wrap(&[7; super::KEY_LEN], recipient).is_err());
+    }
+
+    #[test]
+    fn text() {
+        let identity = Identity::generate();
+        let written = identity.to_string();
+        assert!(written.starts_with("psfr-sk-"));
+
+        let file = format!("# public key: {}\n\n{written}\n", identity.recipient());
+        let parsed = file.parse::<Identity>().unwrap();
+        assert_eq!(parsed.recipient(), identity.recipient());
+        assert!(!format!("{parsed:?}").contains(&written[8..]));
+
+        let recipient = identity.recipient().to_string();
+        assert!(recipient.starts_with("psfr-pk-"));
+        assert_eq!(
+            recipient.parse::<Recipient>().unwrap(),
+            identity.recipient()
+        );
+
+        assert!(recipient.parse::<Identity>().is_err());
+        assert!(written.parse::<Recipient>().is_err());
+        assert!("psfr-pk-00".parse::<Recipient>().is_err());
+        assert!(format!("{written}\n{written}").parse::<Identity>().is_err());
+    }
+}
@@ -0,0 +1,253 @@
+//! Shamir's secret sharing over GF(2^8), so that any `threshold` shares of a data key recover it,
+//! while fewer reveal nothing about it
+//!
+//! Every byte of the key is the constant term of its own random polynomial of degree
+//! `threshold - 1`, and a share holds the value of all of them at the index of the share
+
+use super::Error;
+
+const PREFIX: &str = "psfr-share-";
+
+/// Length of the secret being shared
+const SECRET_LEN: usize = super::kdf::KEY_LEN;
+
+/// One share of a data key
+///
+/// Written as `psfr-share-THRESHOLD-INDEX-` followed by the share in hex
+#[derive(Clone, PartialEq, Eq)]
+pub struct Share {
+    threshold: u8,
+    index: u8,
+    value: [u8; SECRET_LEN],
+}
+
+// The share is as sensitive as the data key, given enough of the others
+impl Drop for Share {
+    fn drop(&mut self) {
+        use zeroize::Zeroize;
+
+        self.value.zeroize();
+    }
+}
+
+impl Share {
+    /// How many shares are needed to recover the data key
+    #[must_use]
+    pub fn threshold(&self) -> u8 {
+        self.threshold
+    }
+
+    /// Position of the share, from `1`
+    #[must_use]
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+}
+
+// Never shows the share itself
+impl std::fmt::Debug for Share {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.debug_struct("Share")
+            .field("threshold", &self.threshold)
+            .field("index", &self.index)
+            .finish_non_exhaustive()
+    }
+}
+
+impl std::fmt::Display for Share {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            fmt,
+            "{PREFIX}{}-{}-{}",
+            self.threshold,
+            self.index,
+            hex::encode(self.value)
+        )
+    }
+}
+
+impl std::str::FromStr for Share {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let mut parts = string
+            .trim()
+            .strip_prefix(PREFIX)
+            .ok_or(Error::InvalidShares)?
+            .splitn(3, '-');
+        let mut number = || {
+            parts
+                .next()
+                .and_then(|part| part.parse::<u8>().ok())
+                .filter(|number| *number > 0)
+                .ok_or(Error::InvalidShares)
+        };
+
+        let mut share = Self {
+            threshold: number()?,
+            index: number()?,
+            value: [0; SECRET_LEN],
+        };
+        hex::decode_to_slice(parts.next().ok_or(Error::InvalidShares)?, &mut share.value)
+            .map_err(|_| Error::InvalidShares)?;
+        Ok(share)
+    }
+}
+
+/// Splits `secret` into `shares` shares, any `threshold` of which recover it
+pub(crate) fn split(
+    secret: &[u8; SECRET_LEN],
+    shares: u8,
+    threshold: u8,
+) -> Result<Vec<Share>, Error> {
+    use rand::RngCore;
+
+    if threshold == 0 || threshold > shares {
+        return Err(Error::InvalidShares);
+    }
+
+    let polynomials = secret
+        .iter()
+        .map(|byte| {
+            let mut coefficients = zeroize::Zeroizing::new(vec![0; usize::from(threshold)]);
+            coefficients[0] = *byte;
+            rand::rngs::OsRng.fill_bytes(&mut coefficients[1..]);
+            coefficients
+        })
+        .collect::<Vec<_>>();
+
+    Ok((1..=shares)
+        .map(|index| {
+            let mut share = Share {
+                threshold,
+                index,
+                value: [0; SECRET_LEN],
+            };
+            for (value, coefficients) in share.value.iter_mut().zip(&polynomials) {
+                // Horner's rule, from the highest coefficient down
+                *value = coefficients
+                    .iter()
+                    .rev()
+                    .fold(0, |value, coefficient| mul(value, index) ^ coefficient);
+            }
+            share
+        })
+        .collect())
+}
+
+/// Recovers the secret from at least `threshold` distinct shares of the same split
+pub(crate) fn combine(shares: &[Share]) -> Result<super::Key, Error> {
+    let threshold = shares.first().ok_or(Error::InvalidShares)?.threshold;
+    let mut chosen = Vec::<&Share>::new();
+    for share in shares {
+        if share.threshold != threshold {
+            return Err(Error::InvalidShares);
+        }
+        if !chosen.iter().any(|chosen| chosen.index == share.index) {
+            chosen.push(share);
+        }
+    }
+    if chosen.len() < usize::from(threshold) {
+        return Err(Error::InvalidShares);
+    }
+    chosen.truncate(usize::from(threshold));
+
+    // Lagrange interpolation at zero, where subtraction is the same as addition
+    let mut secret = super::Key::default();
+    for share in &chosen {
+        let basis = chosen
+            .iter()
+            .filter(|other| other.index != share.index)
+            .fold(1, |basis, other| {
+                mul(basis, mul(other.index, inverse(other.index ^ share.index)))
+            });
+        for (byte, value) in secret.iter_mut().zip(&share.value) {
+            *byte ^= mul(basis, *value);
+        }
+    }
+    Ok(secret)
+}
+
+/// Multiplication modulo the AES polynomial, without branching on the operands
+fn mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    for _ in 0..8 {
+        product ^= a & 0_u8.wrapping_sub(b & 1);
+        let carry = 0_u8.wrapping_sub(a >> 7);
+        a = (a << 1) ^ (carry & 0x1b);
+        b >>= 1;
+    }
+    product
+}
+
+/// Multiplicative inverse, as `a^254`
+fn inverse(a: u8) -> u8 {
+    let mut result = 1;
+    let mut power = a;
+    for bit in 0..8 {
+        if 0b1111_1110 & (1 << bit) != 0 {
+            result = mul(result, power);
+        }
+        power = mul(power, power);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Share;
+
+    #[test]
+    fn field() {
+        assert_eq!(super::mul(0x53, 0xca), 0x01);
+        assert_eq!(super::mul(0x57, 0x83), 0xc1);
+        for a in 1..=255 {
+            assert_eq!(super::mul(a, super::inverse(a)), 1);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let secret = [42; super::SECRET_LEN];
+        let shares = super::split(&secret, 5, 3).unwrap();
+        assert_eq!(shares.len(), 5);
+
+        assert_eq!(*super::combine(&shares).unwrap(), secret);
+        assert_eq!(*super::combine(&shares[2..]).unwrap(), secret);
+        let scattered = [shares[4].clone(), shares[0].clone(), shares[2].clone()];
+        assert_eq!(*super::combine(&scattered).unwrap(), secret);
+
+        assert!(super::combine(&shares[..2]).is_err());
+        let repeated = [shares[0].clone(), shares[0].clone(), shares[1].clone()];
+        assert!(super::combine(&repeated).is_err());
+        assert!(super::combine(&[]).is_err());
+
+        // Shares of another split do not mix
+        let other = super::split(&secret, 5, 2).unwrap();
+        assert!(super::combine(&[shares[0].clone(), other[1].clone(), shares[2].clone()]).is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        let secret = [42; super::SECRET_LEN];
+        assert!(super::split(&secret, 3, 0).is_err());
+        assert!(super::split(&secret, 3, 4).is_err());
+        assert_eq!(super::split(&secret, 1, 1).unwrap()[0].value, secret);
+    }
+
+    #[test]
+    fn text() {
+        let share = &super::split(&[7; super::SECRET_LEN], 3, 2).unwrap()[1];
+        let written = share.to_string();
+        assert!(written.starts_with("psfr-share-2-2-"));
+        assert_eq!(&written.parse::<Share>().unwrap(), share);
+        assert!(!format!("{share:?}").contains(&written[15..]));
+
+        assert!("psfr-share-2-0-00".parse::<Share>().is_err());
+        assert!("psfr-share-2-1-00".parse::<Share>().is_err());
+        assert!(written
+            .replace("psfr-share", "psfr-pk")
+            .parse::<Share>()
+            .is_err());
+    }
+}
@@ -0,0 +1,286 @@
+//! Decoding of entries, records, and stores straight into the buffers that keep them
+//!
+//! Untagged serde enums buffer their whole input into a tree of copies that is never wiped, so
+//! the shapes are told apart by hand here instead. A sequence holds binary data if it is empty or
+//! starts with a byte, and is a record otherwise
+
+use super::metadata::Record;
+use super::{Entry, Secret, Settings, Store};
+
+impl<'de> serde::Deserialize<'de> for Entry {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(EntryVisitor)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Record {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(RecordVisitor)
+    }
+}
+
+/// The secrets of a store, optionally followed by its settings
+pub(crate) struct Stored(pub(crate) Store);
+
+impl<'de> serde::Deserialize<'de> for Stored {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(StoredVisitor)
+    }
+}
+
+struct EntryVisitor;
+
+impl<'de> serde::de::Visitor<'de> for EntryVisitor {
+    type Value = Entry;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter.write_str("a string, binary data, or a nested store")
+    }
+
+    fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        Ok(Entry::String(Secret::new(String::from(value))))
+    }
+
+    fn visit_string<E: serde::de::Error>(self, value: String) -> Result<Self::Value, E> {
+        Ok(Entry::String(Secret::new(value)))
+    }
+
+    fn visit_bytes<E: serde::de::Error>(self, value: &[u8]) -> Result<Self::Value, E> {
+        Ok(Entry::Binary(Secret::new(value.to_vec())))
+    }
+
+    fn visit_byte_buf<E: serde::de::Error>(self, value: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(Entry::Binary(Secret::new(value)))
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+        binary(None, seq)
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        use serde::Deserialize;
+
+        Store::deserialize(serde::de::value::MapAccessDeserializer::new(map)).map(Entry::Nested)
+    }
+}
+
+/// Collects the bytes of binary data, moving them to a new buffer whenever it is full so that
+/// no copy is left behind by a reallocation
+fn binary<'de, A: serde::de::SeqAccess<'de>>(
+    first: Option<u8>,
+    mut seq: A,
+) -> Result<Entry, A::Error> {
+    let hint = seq.size_hint().unwrap_or(0) + usize::from(first.is_some());
+    let mut bytes = zeroize::Zeroizing::new(Vec::with_capacity(hint));
+    bytes.extend(first);
+
+    while let Some(byte) = seq.next_element::<u8>()? {
+        if bytes.len() == bytes.capacity() {
+            let mut grown = zeroize::Zeroizing::new(Vec::with_capacity(bytes.len() * 2 + 8));
+            grown.extend_from_slice(&bytes);
+            bytes = grown;
+        }
+        bytes.push(byte);
+    }
+
+    Ok(Entry::Binary(Secret::new(std::mem::take(&mut *bytes))))
+}
+
+/// First element of a sequence, which tells binary data apart from a record
+enum Head {
+    Byte(u8),
+    Entry(Entry),
+}
+
+impl<'de> serde::Deserialize<'de> for Head {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(HeadVisitor)
+    }
+}
+
+struct HeadVisitor;
+
+impl<'de> serde::de::Visitor<'de> for HeadVisitor {
+    type Value = Head;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter.write_str("a byte or an entry")
+    }
+
+    fn visit_u64<E: serde::de::Error>(self, value: u64) -> Result<Self::Value, E> {
+        use std::convert::TryFrom;
+
+        u8::try_from(value)
+            .map(Head::Byte)
+            .map_err(|_| E::invalid_value(serde::de::Unexpected::Unsigned(value), &self))
+    }
+
+    fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        EntryVisitor.visit_str(value).map(Head::Entry)
+    }
+
+    fn visit_string<E: serde::de::Error>(self, value: String) -> Result<Self::Value, E> {
+        EntryVisitor.visit_string(value).map(Head::Entry)
+    }
+
+    fn visit_bytes<E: serde::de::Error>(self, value: &[u8]) -> Result<Self::Value, E> {
+        EntryVisitor.visit_bytes(value).map(Head::Entry)
+    }
+
+    fn visit_byte_buf<E: serde::de::Error>(self, value: Vec<u8>) -> Result<Self::Value, E> {
+        EntryVisitor.visit_byte_buf(value).map(Head::Entry)
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+        EntryVisitor.visit_seq(seq).map(Head::Entry)
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        EntryVisitor.visit_map(map).map(Head::Entry)
+    }
+}
+
+struct RecordVisitor;
+
+impl RecordVisitor {
+    fn bare(entry: Entry) -> Record {
+        Record {
+            entry,
+            metadata: super::Metadata::default(),
+            history: Vec::new(),
+        }
+    }
+}
+
+impl<'de> serde::de::Visitor<'de> for RecordVisitor {
+    type Value = Record;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter.write_str("an entry, optionally followed by its metadata and history")
+    }
+
+    fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        EntryVisitor.visit_str(value).map(Self::bare)
+    }
+
+    fn visit_string<E: serde::de::Error>(self, value: String) -> Result<Self::Value, E> {
+        EntryVisitor.visit_string(value).map(Self::bare)
+    }
+
+    fn visit_bytes<E: serde::de::Error>(self, value: &[u8]) -> Result<Self::Value, E> {
+        EntryVisitor.visit_bytes(value).map(Self::bare)
+    }
+
+    fn visit_byte_buf<E: serde::de::Error>(self, value: Vec<u8>) -> Result<Self::Value, E> {
+        EntryVisitor.visit_byte_buf(value).map(Self::bare)
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        use serde::de::Error;
+
+        let entry = match seq.next_element::<Head>()? {
+            None => return binary(None, seq).map(Self::bare),
+            Some(Head::Byte(byte)) => return binary(Some(byte), seq).map(Self::bare),
+            Some(Head::Entry(entry)) => entry,
+        };
+        let metadata = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::invalid_length(1, &self))?;
+        let history = seq.next_element()?.unwrap_or_default();
+        if seq.next_element::<serde::de::IgnoredAny>()?.is_some() {
+            return Err(A::Error::invalid_length(4, &self));
+        }
+
+        Ok(Record {
+            entry,
+            metadata,
+            history,
+        })
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        EntryVisitor.visit_map(map).map(Self::bare)
+    }
+}
+
+struct StoredVisitor;
+
+impl<'de> serde::de::Visitor<'de> for StoredVisitor {
+    type Value = Stored;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        formatter.write_str("a store, optionally followed by its settings")
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        use serde::de::Error;
+
+        let store: Store = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::invalid_length(0, &self))?;
+        let settings: Settings = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::invalid_length(1, &self))?;
+        if seq.next_element::<serde::de::IgnoredAny>()?.is_some() {
+            return Err(A::Error::invalid_length(3, &self));
+        }
+
+        Ok(Stored(Store(store.0, settings)))
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        use serde::Deserialize;
+
+        Store::deserialize(serde::de::value::MapAccessDeserializer::new(map)).map(Stored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Entry, Record, Settings, Stored};
+
+    #[test]
+    fn entries() {
+        let entry = serde_json::from_str::<Entry>(r#""value""#).unwrap();
+        assert_eq!(entry, Entry::String(String::from("value").into()));
+
+        let entry = serde_json::from_str::<Entry>("[]").unwrap();
+        assert_eq!(entry, Entry::Binary(Vec::new().into()));
+
+        let entry = serde_json::from_str::<Entry>(r#"{"inner": [1, 2]}"#).unwrap();
+        let Entry::Nested(nested) = entry else {
+            panic!("Not a nested store");
+        };
+        assert_eq!(
+            nested.read("inner"),
+            Some(&Entry::Binary(vec![1, 2].into()))
+        );
+
+        assert!(serde_json::from_str::<Entry>("1").is_err());
+        assert!(serde_json::from_str::<Entry>("[256]").is_err());
+    }
+
+    #[test]
+    fn records() {
+        let record = serde_json::from_str::<Record>(r#"["value", {}, []]"#).unwrap();
+        assert_eq!(record.entry, Entry::String(String::from("value").into()));
+        assert!(record.history.is_empty());
+
+        assert!(serde_json::from_str::<Record>(r#"["value"]"#).is_err());
+        assert!(serde_json::from_str::<Record>(r#"["value", {}, [], 1]"#).is_err());
+        assert!(serde_json::from_str::<Record>(r#"[1, "value"]"#).is_err());
+    }
+
+    #[test]
+    fn stores() {
+        let Stored(store) = serde_json::from_str(r#"{"a": "b"}"#).unwrap();
+        assert_eq!(*store.settings(), Settings::default());
+
+        let Stored(store) = serde_json::from_str(r#"[{"a": "b"}, {"retention": 2}]"#).unwrap();
+        assert_eq!(
+            store.read("a"),
+            Some(&Entry::String(String::from("b").into()))
+        );
+        assert_eq!(store.settings().retention, 2);
+    }
+}
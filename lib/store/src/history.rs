@@ -0,0 +1,261 @@
+use super::metadata::Record;
+use super::{Entry, Path, Store, StoreError};
+
+/// How many previous values are kept for each secret, unless the store is set otherwise
+pub const DEFAULT_RETENTION: usize = 10;
+
+/// Settings of a store, saved along with its secrets
+///
+/// Only the settings of the root store are saved, and they apply to every nested store
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(default)]
+pub struct Settings {
+    /// How many previous values to keep for each secret
+    pub retention: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            retention: DEFAULT_RETENTION,
+        }
+    }
+}
+
+/// A previous value of a secret
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct Version {
+    /// The value of the secret
+    pub entry: Entry,
+    /// When the value was set, in seconds since the UNIX epoch, if known
+    pub modified: Option<u64>,
+}
+
+impl Record {
+    /// Replaces the value, keeping the previous one in the history
+    pub(crate) fn replace(&mut self, entry: Entry, retention: usize) {
+        let previous = std::mem::replace(&mut self.entry, entry);
+        self.history.push(Version {
+            entry: previous,
+            modified: self.metadata.modified,
+        });
+        self.metadata.mark_modified();
+        self.prune(retention);
+    }
+
+    fn prune(&mut self, keep: usize) {
+        let excess = self.history.len().saturating_sub(keep);
+        self.history.drain(..excess);
+    }
+
+    fn prune_all(&mut self, keep: usize) {
+        self.prune(keep);
+        if let Entry::Nested(nested) = &mut self.entry {
+            for record in nested.0.values_mut() {
+                record.prune_all(keep);
+            }
+        }
+    }
+}
+
+impl Store {
+    /// The settings of the store
+    #[must_use]
+    pub fn settings(&self) -> &Settings {
+        &self.1
+    }
+
+    /// Loads the settings of the store
+    pub fn settings_mut(&mut self) -> &mut Settings {
+        &mut self.1
+    }
+
+    /// Previous values of a secret, from the oldest to the most recent, if it exists
+    pub fn history<S: AsRef<str>>(&self, name: S) -> Option<&[Version]> {
+        self.0
+            .get(name.as_ref())
+            .map(|record| record.history.as_slice())
+    }
+
+    /// Loads the previous values of a secret, if it exists
+    pub fn history_mut<S: AsRef<str>>(&mut self, name: S) -> Option<&mut Vec<Version>> {
+        self.0
+            .get_mut(name.as_ref())
+            .map(|record| &mut record.history)
+    }
+
+    /// Previous values of the secret at the given path, from the oldest to the most recent
+    ///
+    /// # Errors
+    /// Same as [`read_at`](#method.read_at)
+    pub fn history_at(&self, path: &Path) -> Result<&[Version], StoreError> {
+        let (name, parents) = path.segments().split_last().ok_or(StoreError::EmptyPath)?;
+        self.nested(path, parents)?
+            .history(name)
+            .ok_or(StoreError::SecretNotFound)
+    }
+
+    /// Restores a previous value of the secret at the given path
+    ///
+    /// Versions count back from the most recent previous value, which is version 1. The current
+    /// value becomes the most recent previous value, so that rolling back can be undone
+    ///
+    /// # Errors
+    /// * Version does not exist: [`VersionNotFound`](enum.StoreError.html#variant.VersionNotFound)
+    /// * Otherwise, same as [`read_at`](#method.read_at)
+    pub fn rollback_at(&mut self, path: &Path, version: usize) -> Result<(), StoreError> {
+        let retention = self.1.retention;
+        let (name, parents) = path.segments().split_last().ok_or(StoreError::EmptyPath)?;
+        let record = self
+            .nested_mut(path, parents)?
+            .0
+            .get_mut(name)
+            .ok_or(StoreError::SecretNotFound)?;
+
+        let index = record
+            .history
+            .len()
+            .checked_sub(version)
+            .filter(|_| version > 0)
+            .ok_or(StoreError::VersionNotFound)?;
+        let entry = record.history[index].entry.clone();
+        record.replace(entry, retention);
+        Ok(())
+    }
+
+    /// Drops all but the `keep` most recent previous values of every secret under the given path
+    ///
+    /// The empty path prunes the whole store
+    ///
+    /// # Errors
+    /// Same as [`read_at`](#method.read_at), except for the empty path
+    pub fn prune_history_at(&mut self, path: &Path, keep: usize) -> Result<(), StoreError> {
+        if let Some((name, parents)) = path.segments().split_last() {
+            self.nested_mut(path, parents)?
+                .0
+                .get_mut(name)
+                .ok_or(StoreError::SecretNotFound)?
+                .prune_all(keep);
+        } else {
+            for record in self.0.values_mut() {
+                record.prune_all(keep);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Entry, Path, Store, StoreError};
+
+    macro_rules! path {
+        ($($string:literal),*) => {
+            &vec![$($string),*].into_iter().collect::<Path>()
+        };
+    }
+
+    macro_rules! entry {
+        ($string:literal) => {
+            Entry::String(String::from($string).into())
+        };
+    }
+
+    fn values(store: &Store, path: &Path) -> Vec<String> {
+        store
+            .history_at(path)
+            .unwrap()
+            .iter()
+            .map(|version| match &version.entry {
+                Entry::String(string) => string.expose().clone(),
+                entry => entry.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keeps_previous_values() {
+        let mut store = Store::new();
+        store.create_at(path!["a", "b"], entry!("first")).unwrap();
+        assert!(values(&store, path!["a", "b"]).is_empty());
+
+        store.update_at(path!["a", "b"], entry!("second")).unwrap();
+        store.update("a", Entry::Nested(Store::new())).unwrap();
+        store.update_at(path!["a"], entry!("third")).unwrap();
+
+        assert_eq!(store.history("a").unwrap().len(), 2);
+        assert_eq!(values(&store, path!["a"])[1], "[Nested store]");
+    }
+
+    #[test]
+    fn retention() {
+        let mut store = Store::new();
+        store.settings_mut().retention = 2;
+        store.create_at(path!["a", "b"], entry!("0")).unwrap();
+        for value in &["1", "2", "3"] {
+            store
+                .update_at(path!["a", "b"], Entry::String(String::from(*value).into()))
+                .unwrap();
+        }
+
+        assert_eq!(values(&store, path!["a", "b"]), ["1", "2"]);
+
+        store.prune_history_at(&Path::new(), 1).unwrap();
+        assert_eq!(values(&store, path!["a", "b"]), ["2"]);
+
+        store.prune_history_at(path!["a"], 0).unwrap();
+        assert!(values(&store, path!["a", "b"]).is_empty());
+        assert_eq!(
+            store.prune_history_at(path!["b"], 0).unwrap_err(),
+            StoreError::SecretNotFound
+        );
+    }
+
+    #[test]
+    fn rollback() {
+        let mut store = Store::new();
+        store.create(String::from("a"), entry!("first")).unwrap();
+        store.update("a", entry!("second")).unwrap();
+        store.update("a", entry!("third")).unwrap();
+
+        store.rollback_at(path!["a"], 2).unwrap();
+        assert_eq!(store.read("a").unwrap(), &entry!("first"));
+        assert_eq!(values(&store, path!["a"]), ["first", "second", "third"]);
+
+        // Rolling back is itself undone by rolling back
+        store.rollback_at(path!["a"], 1).unwrap();
+        assert_eq!(store.read("a").unwrap(), &entry!("third"));
+
+        assert_eq!(
+            store.rollback_at(path!["a"], 0).unwrap_err(),
+            StoreError::VersionNotFound
+        );
+        assert_eq!(
+            store.rollback_at(path!["a"], 6).unwrap_err(),
+            StoreError::VersionNotFound
+        );
+        assert_eq!(
+            store.rollback_at(path!["b"], 1).unwrap_err(),
+            StoreError::SecretNotFound
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut store = Store::new();
+        store.settings_mut().retention = 3;
+        store.create(String::from("a"), entry!("first")).unwrap();
+        store.update("a", entry!("second")).unwrap();
+
+        let bytes = store.encrypt("mega-pass").unwrap();
+        let recovered = Store::decrypt(&bytes, "mega-pass").unwrap();
+        assert_eq!(recovered.settings().retention, 3);
+        assert_eq!(recovered.history("a"), store.history("a"));
+
+        // Nested stores never carry settings of their own
+        let json = serde_json::to_string(&store).unwrap();
+        let recovered = serde_json::from_str::<Store>(&json).unwrap();
+        assert_eq!(recovered.settings(), &super::Settings::default());
+        assert_eq!(recovered.history("a"), store.history("a"));
+    }
+}
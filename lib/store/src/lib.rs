@@ -2,10 +2,28 @@
 
 //! Handles secrets in a secret store
 
+mod decode;
+mod history;
+mod metadata;
+mod ops;
+mod path;
+mod secret;
+
+pub use history::{Settings, Version, DEFAULT_RETENTION};
+pub use metadata::{Metadata, Record};
+pub use ops::Prune;
+pub use path::Path;
+pub use secret::Secret;
+
+pub use crypter::Cipher;
+pub use crypter::Compression;
+pub use crypter::Crypter;
 pub use crypter::Error as CryptoError;
+pub use crypter::Kdf;
+pub use crypter::{Identity, Recipient, Share};
 
 /// Errors that may happen
-#[derive(thiserror::Error, Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(thiserror::Error, Debug, PartialEq, Eq, Clone)]
 pub enum StoreError {
     /// Secret already exists
     #[error("Secret already exists")]
@@ -14,20 +32,58 @@ pub enum StoreError {
     /// Secret not found
     #[error("Secret not found")]
     SecretNotFound,
+
+    /// Path has no segments
+    #[error("Path is empty")]
+    EmptyPath,
+
+    /// Path could not be parsed
+    #[error("Invalid path: {0}")]
+    InvalidPath(&'static str),
+
+    /// Version not found in the history of a secret
+    #[error("Version not found")]
+    VersionNotFound,
+
+    /// A secret cannot be moved to a path inside of itself
+    #[error("Cannot move a secret into itself")]
+    MoveIntoItself,
+
+    /// Nested stores must hold at least one secret
+    #[error("Nothing to add")]
+    EmptyNestedStore,
+
+    /// A path goes through a secret that is not a nested store
+    #[error("Not a nested store: {at}")]
+    NotANestedStore {
+        /// Path to the secret that is not a nested store
+        at: Path,
+    },
 }
 
 /// A secret store that can be loaded from a byte array and stored back into a byte array
-#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq, Clone)]
-pub struct Store(std::collections::HashMap<String, Entry>);
+///
+/// Secrets are kept sorted by name, so iteration and serialization are deterministic and
+/// identical stores always serialize to identical bytes
+///
+/// Every secret carries its [`Metadata`](struct.Metadata.html) and a bounded history of its
+/// previous values. Equality only compares the names and values of the secrets
+#[derive(Debug, Clone)]
+pub struct Store(
+    std::collections::BTreeMap<String, metadata::Record>,
+    history::Settings,
+);
 
 /// Possible values that can be stored in the secret store
-#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq, Clone)]
+///
+/// Values are kept as [`Secret`](struct.Secret.html)s, so neither `Debug` nor `Display` show them
+#[derive(serde::Serialize, Debug, PartialEq, Eq, Clone)]
 #[serde(untagged)]
 pub enum Entry {
     /// Plain string
-    String(String),
+    String(Secret<String>),
     /// Binary data
-    Binary(Vec<u8>),
+    Binary(Secret<Vec<u8>>),
     /// A nested secret store
     Nested(Store),
 }
@@ -35,7 +91,7 @@ pub enum Entry {
 impl std::fmt::Display for Entry {
     fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::String(string) => string.fmt(fmt),
+            Self::String(_) => write!(fmt, "[String]"),
             Self::Binary(_) => write!(fmt, "[Binary data]"),
             Self::Nested(_) => write!(fmt, "[Nested store]"),
         }
@@ -46,18 +102,30 @@ impl Store {
     /// Creates a new empty store
     #[must_use]
     pub fn new() -> Self {
-        Self(std::collections::HashMap::new())
+        Self(std::collections::BTreeMap::new(), Settings::default())
     }
 
     /// Decrypt a secret store from bytes with the given passphrase
     ///
-    /// The bytes are expected to be encrypted, compressed, and encoded in raw binary
+    /// The bytes are expected to be encrypted, compressed, and encoded in raw binary, as
+    /// described by the header at the start of the data
     ///
     /// # Errors
     /// Any decryption, deserialization, and decrompression failures will result in an
-    /// [`CryptoError`](enum.CryptoError.html)
+    /// [`CryptoError`](enum.CryptoError.html). Data written by a newer, unknown format version
+    /// results in [`UnsupportedVersion`](enum.CryptoError.html#variant.UnsupportedVersion)
     pub fn decrypt<S: AsRef<str>>(data: &[u8], pass: S) -> Result<Self, CryptoError> {
-        crypter::Crypter::new(pass).decrypt(data)
+        Self::decrypt_with(data, &crypter::Crypter::new(pass))
+    }
+
+    /// Decrypt a secret store from bytes with a configured [`Crypter`](struct.Crypter.html)
+    ///
+    /// Allows keeping the derived key around to save the store back later
+    ///
+    /// # Errors
+    /// Same as [`decrypt`](#method.decrypt)
+    pub fn decrypt_with(data: &[u8], crypter: &Crypter) -> Result<Self, CryptoError> {
+        crypter.decrypt(data).map(|decode::Stored(store)| store)
     }
 
     /// Encrypt the secret store into bytes with the given passphrase
@@ -68,45 +136,86 @@ impl Store {
     /// Any encryption, serialization, and crompression failures will result in an
     /// [`CryptoError`](enum.CryptoError.html)
     pub fn encrypt<S: AsRef<str>>(&self, pass: S) -> Result<Vec<u8>, CryptoError> {
-        crypter::Crypter::new(pass).encrypt(self)
+        self.encrypt_with(&crypter::Crypter::new(pass))
     }
 
-    /// Creates a new secret in the store
+    /// Encrypt the secret store into bytes with a configured [`Crypter`](struct.Crypter.html)
+    ///
+    /// Allows choosing the [`Compression`](enum.Compression.html) and key derivation
+    ///
+    /// Settings other than the defaults are written as a `[store, settings]` pair, which cannot be
+    /// confused with a plain store
+    ///
+    /// # Errors
+    /// Any encryption, serialization, and crompression failures will result in an
+    /// [`CryptoError`](enum.CryptoError.html)
+    pub fn encrypt_with(&self, crypter: &Crypter) -> Result<Vec<u8>, CryptoError> {
+        if self.1 == Settings::default() {
+            crypter.encrypt(self)
+        } else {
+            crypter.encrypt(&(self, &self.1))
+        }
+    }
+
+    /// Creates a new secret in the store, stamping its creation time
     ///
     /// # Errors
     /// If the secret name already exists, a
     /// [`SecretAlreadyExists`](enum.StoreError.html#variant.SecretAlreadyExists) error will be returned
     pub fn create(&mut self, name: String, entry: Entry) -> Result<(), StoreError> {
         match self.0.entry(name) {
-            std::collections::hash_map::Entry::Vacant(vacant) => {
-                vacant.insert(entry);
+            std::collections::btree_map::Entry::Vacant(vacant) => {
+                vacant.insert(metadata::Record::new(entry));
                 Ok(())
             }
-            std::collections::hash_map::Entry::Occupied(_) => Err(StoreError::SecretAlreadyExists),
+            std::collections::btree_map::Entry::Occupied(_) => Err(StoreError::SecretAlreadyExists),
         }
     }
 
     /// Reads a secret from the store, if it exists
     pub fn read<S: AsRef<str>>(&self, name: S) -> Option<&Entry> {
-        self.0.get(name.as_ref())
+        self.0.get(name.as_ref()).map(|record| &record.entry)
     }
 
     /// Loads a secret from the store, if it exists
+    ///
+    /// Changes made through the returned reference do not update the modification time
     pub fn get<S: AsRef<str>>(&mut self, name: S) -> Option<&mut Entry> {
-        self.0.get_mut(name.as_ref())
+        self.0
+            .get_mut(name.as_ref())
+            .map(|record| &mut record.entry)
     }
 
-    /// Updates a secret from the store
+    /// Reads the metadata of a secret, if it exists
+    pub fn metadata<S: AsRef<str>>(&self, name: S) -> Option<&Metadata> {
+        self.0.get(name.as_ref()).map(|record| &record.metadata)
+    }
+
+    /// Loads the metadata of a secret, if it exists
+    pub fn metadata_mut<S: AsRef<str>>(&mut self, name: S) -> Option<&mut Metadata> {
+        self.0
+            .get_mut(name.as_ref())
+            .map(|record| &mut record.metadata)
+    }
+
+    /// Updates a secret from the store, stamping its modification time
+    ///
+    /// The previous value is kept in the history of the secret
     ///
     /// # Errors
     /// If the secret name does not exist, a
     /// [`SecretNotFound`](enum.StoreError.html#variant.SecretNotFound) error will be returned
     pub fn update<S: AsRef<str>>(&mut self, name: S, new_entry: Entry) -> Result<(), StoreError> {
-        let entry = self
-            .0
-            .get_mut(name.as_ref())
-            .ok_or(StoreError::SecretNotFound)?;
-        *entry = new_entry;
+        let retention = self.1.retention;
+        self.replace(name.as_ref(), new_entry, retention)
+    }
+
+    /// Updates a secret, keeping at most `retention` previous values
+    fn replace(&mut self, name: &str, entry: Entry, retention: usize) -> Result<(), StoreError> {
+        self.0
+            .get_mut(name)
+            .ok_or(StoreError::SecretNotFound)?
+            .replace(entry, retention);
         Ok(())
     }
 
@@ -118,26 +227,66 @@ impl Store {
     pub fn delete<S: AsRef<str>>(&mut self, name: S) -> Result<Entry, StoreError> {
         self.0
             .remove(name.as_ref())
+            .map(|record| record.entry)
             .ok_or(StoreError::SecretNotFound)
     }
 
-    /// An iterator over all the secret names stored
+    /// Whether the store holds no secrets
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// An iterator over all the secret names stored, in ascending order
     pub fn secrets(&self) -> impl Iterator<Item = &String> {
         self.0.keys()
     }
 
-    /// An iterator over all the secret name/value pairs
+    /// An iterator over all the secret name/value pairs, in ascending order of name
     pub fn iter(&self) -> impl Iterator<Item = (&String, &Entry)> {
-        self.0.iter()
+        self.0.iter().map(|(name, record)| (name, &record.entry))
+    }
+}
+
+// Settings are only saved for the root store, by `encrypt_with`
+impl serde::Serialize for Store {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Store {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        serde::Deserialize::deserialize(deserializer)
+            .map(|secrets| Self(secrets, Settings::default()))
+    }
+}
+
+impl PartialEq for Store {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
     }
 }
 
+impl Eq for Store {}
+
 impl IntoIterator for Store {
     type Item = (String, Entry);
-    type IntoIter = std::collections::hash_map::IntoIter<String, Entry>;
+    type IntoIter = IntoIter;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.0.into_iter()
+        IntoIter(self.0.into_iter())
+    }
+}
+
+/// An owning iterator over the secret name/value pairs of a store, in ascending order of name
+pub struct IntoIter(std::collections::btree_map::IntoIter<String, metadata::Record>);
+
+impl Iterator for IntoIter {
+    type Item = (String, Entry);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(name, record)| (name, record.entry))
     }
 }
 
@@ -149,6 +298,8 @@ impl Default for Store {
 
 #[cfg(test)]
 mod tests {
+    use super::Compression;
+    use super::Crypter;
     use super::Entry;
     use super::Store;
     use super::StoreError;
@@ -161,25 +312,35 @@ mod tests {
 
     macro_rules! entry {
         ($string:literal) => {
-            Entry::String(String::from($string))
+            Entry::String(String::from($string).into())
         };
     }
 
-    fn setup() -> (Store, std::collections::HashMap<String, Entry>) {
-        let mut reference = std::collections::HashMap::new();
+    fn setup() -> (Store, std::collections::BTreeMap<String, Entry>) {
+        let mut reference = std::collections::BTreeMap::new();
         reference.insert(own!("existing"), entry!("existing_value"));
 
-        let store = Store(reference.clone());
+        let mut store = Store::new();
+        store
+            .create(own!("existing"), entry!("existing_value"))
+            .unwrap();
 
         (store, reference)
     }
 
     fn new_store() -> Store {
-        let mut map = std::collections::HashMap::new();
-        map.insert(own!("foo1"), entry!("bar1"));
-        map.insert(own!("foo2"), entry!("bar2"));
-        map.insert(own!("foo3"), entry!("bar3"));
-        Store(map)
+        let mut store = Store::new();
+        store.create(own!("foo1"), entry!("bar1")).unwrap();
+        store.create(own!("foo2"), entry!("bar2")).unwrap();
+        store.create(own!("foo3"), entry!("bar3")).unwrap();
+        store
+    }
+
+    fn entries(store: &Store) -> std::collections::BTreeMap<String, Entry> {
+        store
+            .iter()
+            .map(|(name, entry)| (name.clone(), entry.clone()))
+            .collect()
     }
 
     #[test]
@@ -192,11 +353,11 @@ mod tests {
                 .unwrap_err(),
             StoreError::SecretAlreadyExists
         );
-        assert_eq!(store.0, reference);
+        assert_eq!(entries(&store), reference);
 
         assert!(store.create(own!("new"), entry!("new_value")).is_ok());
         reference.insert(own!("new"), entry!("new_value"));
-        assert_eq!(store.0, reference);
+        assert_eq!(entries(&store), reference);
 
         assert_eq!(
             store
@@ -204,33 +365,30 @@ mod tests {
                 .unwrap_err(),
             StoreError::SecretAlreadyExists
         );
-        assert_eq!(store.0, reference);
+        assert_eq!(entries(&store), reference);
     }
 
     #[test]
     fn read() {
         let (store, reference) = setup();
         assert!(store.read("new").is_none());
-        assert_eq!(store.0, reference);
-        assert_eq!(
-            store.read("existing").unwrap().to_string(),
-            "existing_value"
-        );
-        assert_eq!(store.0, reference);
+        assert_eq!(entries(&store), reference);
+        assert_eq!(store.read("existing").unwrap(), &entry!("existing_value"));
+        assert_eq!(entries(&store), reference);
     }
 
     #[test]
     fn get() {
         let (mut store, mut reference) = setup();
         assert!(store.get("new").is_none());
-        assert_eq!(store.0, reference);
-        assert_eq!(store.get("existing").unwrap().to_string(), "existing_value");
-        assert_eq!(store.0, reference);
+        assert_eq!(entries(&store), reference);
+        assert_eq!(store.get("existing").unwrap(), &entry!("existing_value"));
+        assert_eq!(entries(&store), reference);
 
         let entry = store.get("existing").unwrap();
         *entry = entry!("new_value");
         reference.insert(own!("existing"), entry!("new_value"));
-        assert_eq!(store.0, reference);
+        assert_eq!(entries(&store), reference);
     }
 
     #[test]
@@ -241,11 +399,11 @@ mod tests {
             store.update("new", entry!("new_value")).unwrap_err(),
             StoreError::SecretNotFound
         );
-        assert_eq!(store.0, reference);
+        assert_eq!(entries(&store), reference);
 
         assert!(store.update("existing", entry!("new_value")).is_ok());
         reference.insert(own!("existing"), entry!("new_value"));
-        assert_eq!(store.0, reference);
+        assert_eq!(entries(&store), reference);
     }
 
     #[test]
@@ -253,39 +411,106 @@ mod tests {
         let (mut store, mut reference) = setup();
 
         assert_eq!(store.delete("new").unwrap_err(), StoreError::SecretNotFound);
-        assert_eq!(store.0, reference);
+        assert_eq!(entries(&store), reference);
 
         assert!(store.delete("existing").is_ok());
         reference.clear();
-        assert_eq!(store.0, reference);
+        assert_eq!(entries(&store), reference);
     }
 
     #[test]
     fn secrets() {
         let store = new_store();
-        let mut list = store.secrets().collect::<Vec<_>>();
-        list.sort();
+        let list = store.secrets().collect::<Vec<_>>();
         assert_eq!(list, ["foo1", "foo2", "foo3"]);
     }
 
     #[test]
     fn iter() {
         let store = new_store();
-        let mut list = store
-            .iter()
-            .map(|(k, v)| (k, v.to_string()))
-            .collect::<Vec<_>>();
-        list.sort();
+        let list = store.iter().collect::<Vec<_>>();
         assert_eq!(
             list,
             [
-                (&own!("foo1"), own!("bar1")),
-                (&own!("foo2"), own!("bar2")),
-                (&own!("foo3"), own!("bar3"))
+                (&own!("foo1"), &entry!("bar1")),
+                (&own!("foo2"), &entry!("bar2")),
+                (&own!("foo3"), &entry!("bar3"))
             ]
         );
     }
 
+    #[test]
+    fn canonical() {
+        // Timestamps are cleared, since they could differ between the two stores
+        let build = |names: &[&str]| {
+            let mut store = Store::new();
+            for name in names {
+                store.create(String::from(*name), entry!("value")).unwrap();
+                *store.metadata_mut(name).unwrap() = super::Metadata::default();
+            }
+            store
+        };
+        let forward = build(&["b", "a", "c"]);
+        let backward = build(&["c", "a", "b"]);
+
+        assert_eq!(forward.secrets().collect::<Vec<_>>(), ["a", "b", "c"]);
+        assert_eq!(
+            serde_json::to_string(&forward).unwrap(),
+            serde_json::to_string(&backward).unwrap()
+        );
+        assert_eq!(
+            serde_json::to_string(&forward).unwrap(),
+            r#"{"a":"value","b":"value","c":"value"}"#
+        );
+    }
+
+    #[test]
+    fn redacted() {
+        let mut store = Store::new();
+        store.create(own!("password"), entry!("hunter2")).unwrap();
+        store.update("password", entry!("hunter3")).unwrap();
+        store
+            .create_at(
+                &"nested.key".parse().unwrap(),
+                Entry::Binary(b"binary secret".to_vec().into()),
+            )
+            .unwrap();
+
+        let debug = format!("{store:?}");
+        assert!(debug.contains("password"));
+        assert!(debug.contains("String(<redacted>)"));
+        assert!(debug.contains("Binary(<redacted>)"));
+        assert!(!debug.contains("hunter"));
+        assert!(!debug.contains("98, 105, 110"));
+    }
+
+    #[test]
+    fn metadata() {
+        let (mut store, _) = setup();
+        let created = store.metadata("existing").unwrap().clone();
+        assert!(created.created.is_some());
+        assert_eq!(created.modified, created.created);
+        assert!(store.metadata("new").is_none());
+
+        store.update("existing", entry!("new_value")).unwrap();
+        let updated = store.metadata("existing").unwrap();
+        assert_eq!(updated.created, created.created);
+        assert!(updated.modified >= created.modified);
+
+        store.metadata_mut("existing").unwrap().notes = Some(own!("note"));
+        let bytes = store.encrypt("mega-pass").unwrap();
+        let recovered = Store::decrypt(&bytes, "mega-pass").unwrap();
+        assert_eq!(recovered.metadata("existing"), store.metadata("existing"));
+    }
+
+    #[test]
+    fn without_metadata() {
+        let store = serde_json::from_str::<Store>(r#"{"old":"value"}"#).unwrap();
+        assert_eq!(store.read("old").unwrap(), &entry!("value"));
+        assert_eq!(store.metadata("old"), Some(&super::Metadata::default()));
+        assert_eq!(serde_json::to_string(&store).unwrap(), r#"{"old":"value"}"#);
+    }
+
     #[test]
     fn round_trip() {
         let mut store = new_store();
@@ -298,4 +523,55 @@ mod tests {
         let recovered = Store::decrypt(&bytes, "mega-pass").unwrap();
         assert_eq!(store, recovered);
     }
+
+    #[test]
+    fn round_trip_compressed() {
+        let store = new_store();
+
+        for compression in &[Compression::None, Compression::Deflate, Compression::Zstd] {
+            let crypter = Crypter::new("mega-pass")
+                .with_kdf(crypter::Kdf::Pbkdf2 { rounds: 16 })
+                .with_compression(*compression);
+            let bytes = store.encrypt_with(&crypter).unwrap();
+            let recovered = Store::decrypt(&bytes, "mega-pass").unwrap();
+            assert_eq!(store, recovered);
+        }
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn decrypt_never_panics(data: Vec<u8>) {
+            let _ = Store::decrypt(&data, "mega-pass");
+        }
+
+        #[test]
+        fn decrypt_never_panics_on_corruption(index: usize, value: u8, len: usize) {
+            // Cheap derivation so that each case runs quickly
+            let mut bytes = crypter::Crypter::new("mega-pass")
+                .with_kdf(crypter::Kdf::Pbkdf2 { rounds: 16 })
+                .encrypt(&new_store())
+                .unwrap();
+
+            // Leave the derivation identifier and cost alone, as a corrupt cost is bounded but
+            // still allowed to be expensive
+            let index = index % bytes.len();
+            if !(10..15).contains(&index) {
+                bytes[index] = value;
+            }
+            bytes.truncate(len % (bytes.len() + 1));
+
+            let _ = Store::decrypt(&bytes, "mega-pass");
+        }
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let mut bytes = new_store().encrypt("mega-pass").unwrap();
+        bytes[4] = crypter::VERSION + 1;
+
+        assert!(matches!(
+            Store::decrypt(&bytes, "mega-pass"),
+            Err(super::CryptoError::UnsupportedVersion(version)) if version == crypter::VERSION + 1
+        ));
+    }
 }
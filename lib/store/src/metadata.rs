@@ -0,0 +1,128 @@
+use super::{Entry, Version};
+
+/// Bookkeeping attached to every secret in a store
+///
+/// Timestamps are seconds since the UNIX epoch. Secrets written before metadata existed have no
+/// timestamps
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, PartialEq, Eq, Clone)]
+#[serde(default)]
+pub struct Metadata {
+    /// When the secret was created
+    pub created: Option<u64>,
+    /// When the value of the secret last changed
+    pub modified: Option<u64>,
+    /// When the secret was last read
+    pub accessed: Option<u64>,
+    /// Free-form notes
+    pub notes: Option<String>,
+    /// Tags, kept sorted
+    pub tags: std::collections::BTreeSet<String>,
+    /// URL the secret belongs to
+    pub url: Option<String>,
+}
+
+impl Metadata {
+    /// Marks the secret as read now
+    pub fn mark_read(&mut self) {
+        self.accessed = Some(now());
+    }
+
+    pub(crate) fn mark_modified(&mut self) {
+        self.modified = Some(now());
+    }
+
+    fn created_now() -> Self {
+        let now = now();
+        Self {
+            created: Some(now),
+            modified: Some(now),
+            ..Self::default()
+        }
+    }
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+/// A secret value together with its metadata and previous values
+///
+/// Serialized as a bare entry when there is no metadata, which is also how stores without
+/// metadata were written, as an `[entry, metadata]` pair when there is no history, or as an
+/// `[entry, metadata, history]` triple otherwise. These cannot be confused with binary data,
+/// since their first element is never a byte
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Record {
+    /// The value of the secret
+    pub entry: Entry,
+    /// Bookkeeping of the secret
+    pub metadata: Metadata,
+    /// Previous values of the secret, oldest first
+    pub history: Vec<Version>,
+}
+
+impl Record {
+    pub(crate) fn new(entry: Entry) -> Self {
+        Self {
+            entry,
+            metadata: Metadata::created_now(),
+            history: Vec::new(),
+        }
+    }
+}
+
+impl serde::Serialize for Record {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !self.history.is_empty() {
+            (&self.entry, &self.metadata, &self.history).serialize(serializer)
+        } else if self.metadata == Metadata::default() {
+            self.entry.serialize(serializer)
+        } else {
+            (&self.entry, &self.metadata).serialize(serializer)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Entry, Metadata, Record};
+
+    #[test]
+    fn bare_entries() {
+        let record = serde_json::from_str::<Record>(r#""value""#).unwrap();
+        assert_eq!(record.entry, Entry::String(String::from("value").into()));
+        assert_eq!(record.metadata, Metadata::default());
+        assert_eq!(serde_json::to_string(&record).unwrap(), r#""value""#);
+
+        let record = serde_json::from_str::<Record>("[1, 2]").unwrap();
+        assert_eq!(record.entry, Entry::Binary(vec![1, 2].into()));
+        assert_eq!(record.metadata, Metadata::default());
+    }
+
+    #[test]
+    fn with_metadata() {
+        let mut record = Record::new(Entry::Binary(vec![1, 2].into()));
+        record.metadata.tags.insert(String::from("tag"));
+        record.metadata.url = Some(String::from("https://example.com"));
+
+        let json = serde_json::to_string(&record).unwrap();
+        assert!(json.starts_with("[[1,2],{"));
+
+        let recovered = serde_json::from_str::<Record>(&json).unwrap();
+        assert_eq!(recovered.entry, record.entry);
+        assert_eq!(recovered.metadata, record.metadata);
+    }
+
+    #[test]
+    fn timestamps() {
+        let mut metadata = Metadata::created_now();
+        assert!(metadata.created.is_some());
+        assert_eq!(metadata.created, metadata.modified);
+        assert_eq!(metadata.accessed, None);
+
+        metadata.mark_read();
+        assert!(metadata.accessed >= metadata.created);
+    }
+}
@@ -0,0 +1,744 @@
+//! Operations on secrets nested inside stores, addressed by a [`Path`](struct.Path.html)
+
+use super::{metadata::Record, Entry, Metadata, Path, Store, StoreError};
+
+/// What to do with nested stores left empty by an operation
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Prune {
+    /// Keep empty nested stores around
+    Never,
+    /// Delete nested stores left empty, up to but not including the root store
+    EmptyParents,
+}
+
+impl Store {
+    /// Creates a new secret at the given path, creating missing nested stores along the way
+    ///
+    /// # Errors
+    /// * Empty path: [`EmptyPath`](enum.StoreError.html#variant.EmptyPath)
+    /// * Empty nested store: [`EmptyNestedStore`](enum.StoreError.html#variant.EmptyNestedStore)
+    /// * A parent is not a nested store:
+    ///   [`NotANestedStore`](enum.StoreError.html#variant.NotANestedStore)
+    /// * Secret already exists:
+    ///   [`SecretAlreadyExists`](enum.StoreError.html#variant.SecretAlreadyExists)
+    pub fn create_at(&mut self, path: &Path, entry: Entry) -> Result<(), StoreError> {
+        if let Entry::Nested(nested) = &entry {
+            if nested.is_empty() {
+                return Err(StoreError::EmptyNestedStore);
+            }
+        }
+
+        let (name, parents) = path.segments().split_last().ok_or(StoreError::EmptyPath)?;
+
+        let mut store = self;
+        for (index, segment) in parents.iter().enumerate() {
+            store = match &mut store
+                .0
+                .entry(segment.clone())
+                .or_insert_with(|| Record::new(Entry::Nested(Self::new())))
+                .entry
+            {
+                Entry::Nested(inner) => inner,
+                _ => {
+                    return Err(StoreError::NotANestedStore {
+                        at: path.prefix(index + 1),
+                    })
+                }
+            };
+        }
+
+        store.create(name.clone(), entry)
+    }
+
+    /// Reads the secret at the given path
+    ///
+    /// # Errors
+    /// * Empty path: [`EmptyPath`](enum.StoreError.html#variant.EmptyPath)
+    /// * A parent is not a nested store:
+    ///   [`NotANestedStore`](enum.StoreError.html#variant.NotANestedStore)
+    /// * Secret does not exist: [`SecretNotFound`](enum.StoreError.html#variant.SecretNotFound)
+    pub fn read_at(&self, path: &Path) -> Result<&Entry, StoreError> {
+        let (name, parents) = path.segments().split_last().ok_or(StoreError::EmptyPath)?;
+        self.nested(path, parents)?
+            .read(name)
+            .ok_or(StoreError::SecretNotFound)
+    }
+
+    /// Reads the metadata of the secret at the given path
+    ///
+    /// # Errors
+    /// Same as [`read_at`](#method.read_at)
+    pub fn metadata_at(&self, path: &Path) -> Result<&Metadata, StoreError> {
+        let (name, parents) = path.segments().split_last().ok_or(StoreError::EmptyPath)?;
+        self.nested(path, parents)?
+            .metadata(name)
+            .ok_or(StoreError::SecretNotFound)
+    }
+
+    /// Loads the metadata of the secret at the given path
+    ///
+    /// # Errors
+    /// Same as [`read_at`](#method.read_at)
+    pub fn metadata_at_mut(&mut self, path: &Path) -> Result<&mut Metadata, StoreError> {
+        let (name, parents) = path.segments().split_last().ok_or(StoreError::EmptyPath)?;
+        self.nested_mut(path, parents)?
+            .metadata_mut(name)
+            .ok_or(StoreError::SecretNotFound)
+    }
+
+    /// Updates the secret at the given path, keeping the previous value in its history
+    ///
+    /// # Errors
+    /// * Empty path: [`EmptyPath`](enum.StoreError.html#variant.EmptyPath)
+    /// * A parent is not a nested store:
+    ///   [`NotANestedStore`](enum.StoreError.html#variant.NotANestedStore)
+    /// * Secret does not exist: [`SecretNotFound`](enum.StoreError.html#variant.SecretNotFound)
+    pub fn update_at(&mut self, path: &Path, entry: Entry) -> Result<(), StoreError> {
+        let retention = self.1.retention;
+        let (name, parents) = path.segments().split_last().ok_or(StoreError::EmptyPath)?;
+        self.nested_mut(path, parents)?
+            .replace(name, entry, retention)
+    }
+
+    /// Deletes the secret at the given path, returning it
+    ///
+    /// # Errors
+    /// * Empty path: [`EmptyPath`](enum.StoreError.html#variant.EmptyPath)
+    /// * A parent is not a nested store:
+    ///   [`NotANestedStore`](enum.StoreError.html#variant.NotANestedStore)
+    /// * Secret does not exist: [`SecretNotFound`](enum.StoreError.html#variant.SecretNotFound)
+    pub fn delete_at(&mut self, path: &Path, prune: Prune) -> Result<Entry, StoreError> {
+        let (name, parents) = path.segments().split_last().ok_or(StoreError::EmptyPath)?;
+        let entry = self.nested_mut(path, parents)?.delete(name)?;
+
+        if prune == Prune::EmptyParents {
+            for depth in (0..parents.len()).rev() {
+                let parent = self.nested_mut(path, &parents[..depth])?;
+                let name = &parents[depth];
+                if matches!(parent.read(name), Some(Entry::Nested(nested)) if nested.is_empty()) {
+                    parent.delete(name)?;
+                } else {
+                    break;
+                }
+            }
+        }
+
+        Ok(entry)
+    }
+
+    /// Moves the secret at `from` to `to`, along with its metadata and history
+    ///
+    /// # Errors
+    /// * `to` is `from` or inside it:
+    ///   [`MoveIntoItself`](enum.StoreError.html#variant.MoveIntoItself)
+    /// * Otherwise, same as [`read_at`](#method.read_at) for `from` and
+    ///   [`create_at`](#method.create_at) for `to`
+    pub fn move_at(&mut self, from: &Path, to: &Path, prune: Prune) -> Result<(), StoreError> {
+        let (name, parents) = from.segments().split_last().ok_or(StoreError::EmptyPath)?;
+        let record = self
+            .nested(from, parents)?
+            .0
+            .get(name)
+            .ok_or(StoreError::SecretNotFound)?
+            .clone();
+        if to.segments().starts_with(from.segments()) {
+            return Err(StoreError::MoveIntoItself);
+        }
+
+        self.create_at(to, record.entry.clone())?;
+        let (name, parents) = to.segments().split_last().ok_or(StoreError::EmptyPath)?;
+        if let Some(moved) = self.nested_mut(to, parents)?.0.get_mut(name) {
+            *moved = record;
+        }
+        self.delete_at(from, prune).map(|_| ())
+    }
+
+    /// Walks down the `parents` of `path`
+    pub(crate) fn nested(&self, path: &Path, parents: &[String]) -> Result<&Self, StoreError> {
+        let mut store = self;
+        for (index, segment) in parents.iter().enumerate() {
+            store = match store.read(segment) {
+                Some(Entry::Nested(inner)) => inner,
+                Some(_) => {
+                    return Err(StoreError::NotANestedStore {
+                        at: path.prefix(index + 1),
+                    })
+                }
+                None => return Err(StoreError::SecretNotFound),
+            };
+        }
+        Ok(store)
+    }
+
+    /// Walks down the `parents` of `path`, mutably
+    pub(crate) fn nested_mut(
+        &mut self,
+        path: &Path,
+        parents: &[String],
+    ) -> Result<&mut Self, StoreError> {
+        let mut store = self;
+        for (index, segment) in parents.iter().enumerate() {
+            store = match store.get(segment) {
+                Some(Entry::Nested(inner)) => inner,
+                Some(_) => {
+                    return Err(StoreError::NotANestedStore {
+                        at: path.prefix(index + 1),
+                    })
+                }
+                None => return Err(StoreError::SecretNotFound),
+            };
+        }
+        Ok(store)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Entry, Path, Prune, Store};
+
+    static MAP: &str = r#"{
+                            "binary": [ 245, 107, 95, 100 ],
+                            "nested": {
+                              "inner": {
+                                "deep": {
+                                  "foo": "bar"
+                                }
+                              },
+                              "sibling": "inner_sibling"
+                            },
+                            "sibling": "outer_sibling"
+                          }"#;
+
+    macro_rules! own {
+        ($string:literal) => {
+            String::from($string)
+        };
+        (e $string:literal) => {
+            Entry::String(String::from($string).into())
+        };
+    }
+
+    macro_rules! path {
+        ($($string:literal),*) => {
+            &[$($string),*].iter().copied().collect::<Path>()
+        };
+    }
+
+    macro_rules! parse {
+        ($string:expr) => {
+            serde_json::from_str::<Store>($string).unwrap()
+        };
+        (e $string:literal) => {
+            Entry::Nested(parse!($string))
+        };
+    }
+
+    macro_rules! update_empty {
+        ($path:expr) => {{
+            let mut store = make_store();
+            let previous = store.read_at($path).unwrap().clone();
+            store.update_at($path, parse!(e "{}")).unwrap();
+            assert_eq!(store.read_at($path).unwrap(), &parse!(e "{}"));
+            assert_eq!(store.history_at($path).unwrap().last().unwrap().entry, previous);
+        }};
+    }
+
+    macro_rules! delete {
+        ($path:expr, $expected:literal) => {{
+            let mut store = make_store();
+            store.delete_at($path, Prune::EmptyParents).unwrap();
+            assert_eq!(store, parse!($expected));
+        }};
+    }
+
+    fn make_store() -> Store {
+        parse!(MAP)
+    }
+
+    #[test]
+    fn create() {
+        let mut store = Store::new();
+
+        store.create_at(path!["new"], own!(e "new_value")).unwrap();
+        assert_eq!(store, parse!(r#"{"new":"new_value"}"#));
+
+        store.create_at(path!["foo"], own!(e "new_value")).unwrap();
+        assert_eq!(store, parse!(r#"{"new":"new_value","foo":"new_value"}"#));
+
+        store
+            .create_at(path!["nested", "inner", "foo"], own!(e "bar"))
+            .unwrap();
+        assert_eq!(
+            store,
+            parse!(r#"{"new":"new_value","foo":"new_value","nested":{"inner":{"foo":"bar"}}}"#)
+        );
+
+        store
+            .create_at(
+                path!["nested", "other", "foo", "deep", "deeper"],
+                own!(e "here"),
+            )
+            .unwrap();
+        assert_eq!(
+            store,
+            parse!(
+                r#"{"new":"new_value","foo":"new_value","nested":{"inner":{"foo":"bar"},"other":{"foo":{"deep":{"deeper":"here"}}}}}"#
+            )
+        );
+    }
+
+    #[test]
+    fn create_conflict() {
+        let mut store = make_store();
+
+        assert!(store
+            .create_at(path!["binary"], own!(e "new_value"))
+            .is_err());
+        assert!(store
+            .create_at(path!["nested"], own!(e "new_value"))
+            .is_err());
+        assert!(store
+            .create_at(path!["nested", "sibling"], own!(e "new_value"))
+            .is_err());
+        assert!(store
+            .create_at(path!["nested", "sibling", "deep"], own!(e "new_value"))
+            .is_err());
+    }
+
+    #[test]
+    fn create_empty() {
+        let mut store = make_store();
+        assert!(store.create_at(path!["nested"], parse!(e "{}")).is_err());
+    }
+
+    #[test]
+    fn read() {
+        let store = make_store();
+
+        assert_eq!(
+            store.read_at(path!["binary"]).unwrap(),
+            &Entry::Binary(vec![245, 107, 95, 100].into())
+        );
+
+        assert_eq!(
+            store.read_at(path!["nested"]).unwrap(),
+            store.read("nested").unwrap()
+        );
+
+        assert_eq!(
+            store.read_at(path!["nested", "inner"]).unwrap(),
+            &parse!(e r#"{"deep":{"foo":"bar"}}"#)
+        );
+
+        assert_eq!(
+            store.read_at(path!["nested", "inner", "deep"]).unwrap(),
+            &parse!(e r#"{"foo":"bar"}"#)
+        );
+
+        assert_eq!(
+            store
+                .read_at(path!["nested", "inner", "deep", "foo"])
+                .unwrap(),
+            &own!(e "bar")
+        );
+
+        assert_eq!(
+            store.read_at(path!["nested", "sibling"]).unwrap(),
+            &own!(e "inner_sibling")
+        );
+
+        assert_eq!(
+            store.read_at(path!["binary"]).unwrap(),
+            &Entry::Binary(vec![245, 107, 95, 100].into())
+        );
+
+        assert_eq!(
+            store.read_at(path!["sibling"]).unwrap(),
+            &own!(e "outer_sibling")
+        );
+    }
+
+    #[test]
+    fn read_not_found() {
+        let store = make_store();
+
+        assert!(store.read_at(path!["bla"]).is_err());
+        assert!(store.read_at(path!["binary", "245"]).is_err());
+        assert!(store.read_at(path!["nested", "bla"]).is_err());
+        assert!(store.read_at(path!["nested", "bla", "foo"]).is_err());
+        assert!(store.read_at(path!["nested", "inner", "bla"]).is_err());
+        assert!(store
+            .read_at(path!["nested", "inner", "bla", "deep"])
+            .is_err());
+        assert!(store
+            .read_at(path!["nested", "inner", "deep", "bla"])
+            .is_err());
+        assert!(store
+            .read_at(path!["nested", "inner", "deep", "foo", "bla"])
+            .is_err());
+        assert!(store.read_at(path![""]).is_err());
+    }
+
+    #[test]
+    fn update() {
+        let mut store = make_store();
+
+        // update top level
+        store.update_at(path!["binary"], own!(e "new")).unwrap();
+        assert_eq!(
+            store,
+            parse!(
+                r#"{
+                     "binary": "new",
+                     "nested": {
+                       "inner": {
+                         "deep": {
+                           "foo": "bar"
+                         }
+                       },
+                       "sibling": "inner_sibling"
+                     },
+                     "sibling": "outer_sibling"
+                   }"#
+            )
+        );
+
+        // update deep
+        store
+            .update_at(path!["nested", "inner", "deep", "foo"], own!(e "new"))
+            .unwrap();
+        assert_eq!(
+            store,
+            parse!(
+                r#"{
+                     "binary": "new",
+                     "nested": {
+                       "inner": {
+                         "deep": {
+                           "foo": "new"
+                         }
+                       },
+                       "sibling": "inner_sibling"
+                     },
+                     "sibling": "outer_sibling"
+                   }"#
+            )
+        );
+
+        // update root of deep tree
+        store.update_at(path!["nested"], own!(e "new")).unwrap();
+        assert_eq!(
+            store,
+            parse!(
+                r#"{
+                     "binary": "new",
+                     "nested": "new",
+                     "sibling": "outer_sibling"
+                   }"#
+            )
+        );
+    }
+
+    #[test]
+    fn update_empty_keeps_history() {
+        update_empty!(path!["binary"]);
+        update_empty!(path!["sibling"]);
+        update_empty!(path!["nested"]);
+        update_empty!(path!["nested", "sibling"]);
+        update_empty!(path!["nested", "inner"]);
+        update_empty!(path!["nested", "inner", "deep"]);
+        update_empty!(path!["nested", "inner", "deep", "foo"]);
+    }
+
+    #[test]
+    fn update_not_found() {
+        let mut store = make_store();
+
+        assert!(store.update_at(path!["bla"], own!(e "")).is_err());
+        assert!(store.update_at(path!["binary", "245"], own!(e "")).is_err());
+        assert!(store.update_at(path!["nested", "bla"], own!(e "")).is_err());
+        assert!(store
+            .update_at(path!["nested", "bla", "foo"], own!(e ""))
+            .is_err());
+        assert!(store
+            .update_at(path!["nested", "inner", "bla"], own!(e ""))
+            .is_err());
+        assert!(store
+            .update_at(path!["nested", "inner", "bla", "deep"], own!(e ""))
+            .is_err());
+        assert!(store
+            .update_at(path!["nested", "inner", "deep", "bla"], own!(e ""))
+            .is_err());
+        assert!(store
+            .update_at(path!["nested", "inner", "deep", "foo", "bla"], own!(e ""))
+            .is_err());
+        assert!(store.update_at(path![""], own!(e "")).is_err());
+    }
+
+    #[test]
+    fn delete() {
+        delete!(
+            path!["binary"],
+            r#"{
+                 "nested": {
+                   "inner": {
+                     "deep": {
+                       "foo": "bar"
+                     }
+                   },
+                   "sibling": "inner_sibling"
+                 },
+                 "sibling": "outer_sibling"
+               }"#
+        );
+
+        delete!(
+            path!["sibling"],
+            r#"{
+                 "binary": [ 245, 107, 95, 100 ],
+                 "nested": {
+                   "inner": {
+                     "deep": {
+                       "foo": "bar"
+                     }
+                   },
+                   "sibling": "inner_sibling"
+                 }
+               }"#
+        );
+
+        delete!(
+            path!["nested"],
+            r#"{
+                 "binary": [ 245, 107, 95, 100 ],
+                 "sibling": "outer_sibling"
+               }"#
+        );
+
+        delete!(
+            path!["nested", "sibling"],
+            r#"{
+                 "binary": [ 245, 107, 95, 100 ],
+                 "nested": {
+                   "inner": {
+                     "deep": {
+                       "foo": "bar"
+                     }
+                   }
+                 },
+                 "sibling": "outer_sibling"
+               }"#
+        );
+
+        delete!(
+            path!["nested", "inner"],
+            r#"{
+                 "binary": [ 245, 107, 95, 100 ],
+                 "nested": {
+                   "sibling": "inner_sibling"
+                 },
+                 "sibling": "outer_sibling"
+               }"#
+        );
+
+        delete!(
+            path!["nested", "inner", "deep"],
+            r#"{
+                 "binary": [ 245, 107, 95, 100 ],
+                 "nested": {
+                   "sibling": "inner_sibling"
+                 },
+                 "sibling": "outer_sibling"
+               }"#
+        );
+
+        delete!(
+            path!["nested", "inner", "deep", "foo"],
+            r#"{
+                 "binary": [ 245, 107, 95, 100 ],
+                 "nested": {
+                   "sibling": "inner_sibling"
+                 },
+                 "sibling": "outer_sibling"
+               }"#
+        );
+    }
+
+    #[test]
+    fn delete_not_found() {
+        let mut store = make_store();
+
+        assert!(store.delete_at(path!["bla"], Prune::EmptyParents).is_err());
+        assert!(store
+            .delete_at(path!["binary", "245"], Prune::EmptyParents)
+            .is_err());
+        assert!(store
+            .delete_at(path!["nested", "bla"], Prune::EmptyParents)
+            .is_err());
+        assert!(store
+            .delete_at(path!["nested", "bla", "foo"], Prune::EmptyParents)
+            .is_err());
+        assert!(store
+            .delete_at(path!["nested", "inner", "bla"], Prune::EmptyParents)
+            .is_err());
+        assert!(store
+            .delete_at(path!["nested", "inner", "bla", "deep"], Prune::EmptyParents)
+            .is_err());
+        assert!(store
+            .delete_at(path!["nested", "inner", "deep", "bla"], Prune::EmptyParents)
+            .is_err());
+        assert!(store
+            .delete_at(
+                path!["nested", "inner", "deep", "foo", "bla"],
+                Prune::EmptyParents
+            )
+            .is_err());
+        assert!(store.delete_at(path![""], Prune::EmptyParents).is_err());
+    }
+
+    #[test]
+    fn typed_errors() {
+        use super::StoreError;
+
+        let mut store = make_store();
+
+        assert_eq!(
+            store.read_at(&Path::new()).unwrap_err(),
+            StoreError::EmptyPath
+        );
+        assert_eq!(
+            store.read_at(path!["nested", "bla", "foo"]).unwrap_err(),
+            StoreError::SecretNotFound
+        );
+        assert_eq!(
+            store
+                .create_at(path!["nested", "sibling", "deep", "deeper"], own!(e "new"))
+                .unwrap_err(),
+            StoreError::NotANestedStore {
+                at: path!["nested", "sibling"].clone()
+            }
+        );
+        assert_eq!(
+            store
+                .update_at(path!["binary", "245"], own!(e "new"))
+                .unwrap_err(),
+            StoreError::NotANestedStore {
+                at: path!["binary"].clone()
+            }
+        );
+        assert_eq!(
+            store.create_at(path!["new"], parse!(e "{}")).unwrap_err(),
+            StoreError::EmptyNestedStore
+        );
+        assert_eq!(store, make_store());
+    }
+
+    #[test]
+    fn move_at() {
+        use super::StoreError;
+
+        let mut store = make_store();
+        store
+            .update_at(path!["nested", "inner", "deep", "foo"], own!(e "new"))
+            .unwrap();
+        store
+            .move_at(
+                path!["nested", "inner", "deep", "foo"],
+                path!["moved", "foo"],
+                Prune::EmptyParents,
+            )
+            .unwrap();
+
+        assert_eq!(
+            store.read_at(path!["moved", "foo"]).unwrap(),
+            &own!(e "new")
+        );
+        assert_eq!(store.history_at(path!["moved", "foo"]).unwrap().len(), 1);
+        assert_eq!(
+            store.read_at(path!["nested", "inner"]).unwrap_err(),
+            StoreError::SecretNotFound
+        );
+
+        assert_eq!(
+            store
+                .move_at(
+                    path!["nested"],
+                    path!["nested", "inner"],
+                    Prune::EmptyParents
+                )
+                .unwrap_err(),
+            StoreError::MoveIntoItself
+        );
+        assert_eq!(
+            store
+                .move_at(path!["sibling"], path!["moved", "foo"], Prune::EmptyParents)
+                .unwrap_err(),
+            StoreError::SecretAlreadyExists
+        );
+        assert_eq!(
+            store
+                .move_at(path!["missing"], path!["other"], Prune::EmptyParents)
+                .unwrap_err(),
+            StoreError::SecretNotFound
+        );
+    }
+
+    #[test]
+    fn no_pruning() {
+        let mut store = make_store();
+        store
+            .delete_at(path!["nested", "inner", "deep", "foo"], Prune::Never)
+            .unwrap();
+        assert_eq!(
+            store.read_at(path!["nested", "inner", "deep"]).unwrap(),
+            &Entry::Nested(Store::new())
+        );
+
+        let mut store = make_store();
+        store
+            .update_at(path!["nested", "inner"], parse!(e "{}"))
+            .unwrap();
+        assert_eq!(
+            store.read_at(path!["nested", "inner"]).unwrap(),
+            &Entry::Nested(Store::new())
+        );
+    }
+
+    #[test]
+    fn metadata() {
+        let mut store = make_store();
+
+        assert_eq!(
+            store.metadata_at(path!["nested", "sibling"]).unwrap(),
+            &super::Metadata::default()
+        );
+        assert_eq!(
+            store.metadata_at(path!["nested", "bla"]).unwrap_err(),
+            super::StoreError::SecretNotFound
+        );
+
+        store
+            .create_at(path!["nested", "new", "deep"], own!(e "value"))
+            .unwrap();
+        assert!(store
+            .metadata_at(path!["nested", "new", "deep"])
+            .unwrap()
+            .created
+            .is_some());
+        assert!(store
+            .metadata_at(path!["nested", "new"])
+            .unwrap()
+            .created
+            .is_some());
+
+        store
+            .metadata_at_mut(path!["nested", "new", "deep"])
+            .unwrap()
+            .mark_read();
+        assert!(store
+            .metadata_at(path!["nested", "new", "deep"])
+            .unwrap()
+            .accessed
+            .is_some());
+    }
+}
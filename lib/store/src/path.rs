@@ -0,0 +1,290 @@
+use super::StoreError;
+
+/// Location of a secret inside nested stores, as the names of each store along the way
+///
+/// Paths are written as names separated by dots, such as `hosts."example.com".password`. A name
+/// may be quoted, and backslashes escape the next character both inside and outside quotes.
+/// Names are taken as written, including any whitespace, and may only be empty when quoted
+///
+/// Alternatively, paths starting with `/` are read as a JSON Pointer, such as
+/// `/hosts/example.com/password`
+///
+/// Formatting writes the dotted form, quoting names that need it or hold whitespace, and parses
+/// back to the same path
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Default)]
+pub struct Path(Vec<String>);
+
+impl Path {
+    /// Creates an empty path, pointing at the root store
+    #[must_use]
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Appends a segment to the end of the path
+    pub fn push<S: Into<String>>(&mut self, segment: S) {
+        self.0.push(segment.into());
+    }
+
+    /// The names along the path, from the root store
+    #[must_use]
+    pub fn segments(&self) -> &[String] {
+        &self.0
+    }
+
+    /// Number of segments in the path
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Whether the path points at the root store
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Formats the path as a JSON Pointer
+    #[must_use]
+    pub fn to_pointer(&self) -> String {
+        self.0.iter().fold(String::new(), |mut pointer, segment| {
+            pointer.push('/');
+            pointer.push_str(&segment.replace('~', "~0").replace('/', "~1"));
+            pointer
+        })
+    }
+
+    /// The first `len` segments of the path
+    pub(crate) fn prefix(&self, len: usize) -> Self {
+        Self(self.0[..len].to_vec())
+    }
+}
+
+impl std::str::FromStr for Path {
+    type Err = StoreError;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        if let Some(pointer) = string.strip_prefix('/') {
+            parse_pointer(pointer)
+        } else {
+            parse_dotted(string)
+        }
+    }
+}
+
+fn parse_pointer(pointer: &str) -> Result<Path, StoreError> {
+    pointer
+        .split('/')
+        .map(|segment| {
+            let mut unescaped = String::with_capacity(segment.len());
+            let mut chars = segment.chars();
+            while let Some(c) = chars.next() {
+                if c == '~' {
+                    match chars.next() {
+                        Some('0') => unescaped.push('~'),
+                        Some('1') => unescaped.push('/'),
+                        _ => return Err(StoreError::InvalidPath("`~` must be followed by 0 or 1")),
+                    }
+                } else {
+                    unescaped.push(c);
+                }
+            }
+            Ok(unescaped)
+        })
+        .collect::<Result<_, _>>()
+        .map(Path)
+}
+
+fn parse_dotted(string: &str) -> Result<Path, StoreError> {
+    let mut path = Path::new();
+    if string.is_empty() {
+        return Ok(path);
+    }
+
+    let mut chars = string.chars();
+    loop {
+        let mut segment = String::new();
+        let mut quoted = false;
+        let mut next = chars.next();
+
+        if next == Some('"') {
+            quoted = true;
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => segment.push(
+                        chars
+                            .next()
+                            .ok_or(StoreError::InvalidPath("Dangling escape"))?,
+                    ),
+                    Some(c) => segment.push(c),
+                    None => return Err(StoreError::InvalidPath("Unterminated quote")),
+                }
+            }
+            next = chars.next();
+        } else {
+            while let Some(c) = next {
+                match c {
+                    '.' => break,
+                    '"' => return Err(StoreError::InvalidPath("Quote inside a name")),
+                    '\\' => segment.push(
+                        chars
+                            .next()
+                            .ok_or(StoreError::InvalidPath("Dangling escape"))?,
+                    ),
+                    c => segment.push(c),
+                }
+                next = chars.next();
+            }
+        }
+
+        if segment.is_empty() && !quoted {
+            return Err(StoreError::InvalidPath("Empty name"));
+        }
+        path.push(segment);
+
+        match next {
+            None => return Ok(path),
+            Some('.') => {}
+            Some(_) => return Err(StoreError::InvalidPath("Expected `.` after a quoted name")),
+        }
+    }
+}
+
+impl std::fmt::Display for Path {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use std::fmt::Write;
+
+        for (index, segment) in self.0.iter().enumerate() {
+            if index > 0 {
+                fmt.write_char('.')?;
+            }
+
+            let quote = segment.is_empty()
+                || segment.contains(&['.', '"', '\\'][..])
+                || segment.contains(char::is_whitespace)
+                || (index == 0 && segment.starts_with('/'));
+
+            if quote {
+                fmt.write_char('"')?;
+                for c in segment.chars() {
+                    if c == '"' || c == '\\' {
+                        fmt.write_char('\\')?;
+                    }
+                    fmt.write_char(c)?;
+                }
+                fmt.write_char('"')?;
+            } else {
+                fmt.write_str(segment)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl AsRef<[String]> for Path {
+    fn as_ref(&self) -> &[String] {
+        &self.0
+    }
+}
+
+impl From<Vec<String>> for Path {
+    fn from(segments: Vec<String>) -> Self {
+        Self(segments)
+    }
+}
+
+impl<S: Into<String>> std::iter::FromIterator<S> for Path {
+    fn from_iter<I: IntoIterator<Item = S>>(iter: I) -> Self {
+        Self(iter.into_iter().map(Into::into).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Path;
+
+    macro_rules! path {
+        ($($string:literal),*) => {
+            vec![$($string),*].into_iter().collect::<Path>()
+        };
+    }
+
+    #[test]
+    fn parse_dotted() {
+        assert_eq!("".parse::<Path>().unwrap(), Path::new());
+        assert_eq!("a".parse::<Path>().unwrap(), path!["a"]);
+        assert_eq!("a.b.c".parse::<Path>().unwrap(), path!["a", "b", "c"]);
+        assert_eq!(" a . b ".parse::<Path>().unwrap(), path![" a ", " b "]);
+        assert_eq!(
+            r#"hosts."example.com".password"#.parse::<Path>().unwrap(),
+            path!["hosts", "example.com", "password"]
+        );
+        assert_eq!(
+            r"hosts.example\.com.password".parse::<Path>().unwrap(),
+            path!["hosts", "example.com", "password"]
+        );
+        assert_eq!(
+            r#""".""."a\"b"."\\""#.parse::<Path>().unwrap(),
+            path!["", "", "a\"b", "\\"]
+        );
+        assert_eq!(
+            r#"a\\b.c\"d"#.parse::<Path>().unwrap(),
+            path!["a\\b", "c\"d"]
+        );
+    }
+
+    #[test]
+    fn parse_dotted_invalid() {
+        for invalid in &[
+            ".", "a.", ".a", "a..b", "\"a", "a\"b\"", "\"a\"b", "a\\", "\"a\\",
+        ] {
+            assert!(invalid.parse::<Path>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn parse_pointer() {
+        assert_eq!("/".parse::<Path>().unwrap(), path![""]);
+        assert_eq!(
+            "/hosts/example.com/password".parse::<Path>().unwrap(),
+            path!["hosts", "example.com", "password"]
+        );
+        assert_eq!(
+            "/a~1b/c~0d/~01".parse::<Path>().unwrap(),
+            path!["a/b", "c~d", "~1"]
+        );
+        assert!("/a~2".parse::<Path>().is_err());
+        assert!("/a~".parse::<Path>().is_err());
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(path!["a", "b"].to_string(), "a.b");
+        assert_eq!(
+            path!["hosts", "example.com", "password"].to_string(),
+            r#"hosts."example.com".password"#
+        );
+        assert_eq!(path![" padded "].to_string(), r#"" padded ""#);
+        assert_eq!(path!["", "a\"b\\"].to_string(), r#"""."a\"b\\""#);
+        assert_eq!(path!["/root", "/inner"].to_string(), r#""/root"./inner"#);
+        assert_eq!(Path::new().to_string(), "");
+    }
+
+    #[test]
+    fn round_trip() {
+        let paths = [
+            path!["a", "b"],
+            path!["example.com"],
+            path![" padded ", "", "\"quoted\""],
+            path!["/root", "back\\slash", "~tilde"],
+            path!["\u{e9}t\u{e9}", "a b"],
+        ];
+
+        for path in &paths {
+            assert_eq!(&path.to_string().parse::<Path>().unwrap(), path);
+            assert_eq!(&path.to_pointer().parse::<Path>().unwrap(), path);
+        }
+    }
+}
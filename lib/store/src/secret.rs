@@ -0,0 +1,80 @@
+//! Wrapper that keeps secret values out of logs and wipes them from memory
+
+/// A secret value, only reachable through [`expose`](#method.expose)
+///
+/// `Debug` and `Display` show `<redacted>` instead of the value, and the value is wiped from
+/// memory when dropped. Serialization writes the value as is, since that is how stores are saved
+#[derive(Clone, PartialEq, Eq, Default)]
+pub struct Secret<T: zeroize::Zeroize>(T);
+
+impl<T: zeroize::Zeroize> Secret<T> {
+    /// Wraps `value`
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    /// The value itself, which should only be asked for to use it on purpose
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: zeroize::Zeroize> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T: zeroize::Zeroize> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl<T: zeroize::Zeroize> std::fmt::Debug for Secret<T> {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.write_str("<redacted>")
+    }
+}
+
+impl<T: zeroize::Zeroize> std::fmt::Display for Secret<T> {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.write_str("<redacted>")
+    }
+}
+
+impl<T: zeroize::Zeroize + serde::Serialize> serde::Serialize for Secret<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de, T: zeroize::Zeroize + serde::Deserialize<'de>> serde::Deserialize<'de> for Secret<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Secret;
+
+    #[test]
+    fn redacted() {
+        let secret = Secret::new(String::from("hunter2"));
+        assert_eq!(format!("{secret:?}"), "<redacted>");
+        assert_eq!(secret.to_string(), "<redacted>");
+        assert_eq!(secret.expose(), "hunter2");
+    }
+
+    #[test]
+    fn transparent() {
+        let secret = Secret::from(vec![1_u8, 2]);
+        let json = serde_json::to_string(&secret).unwrap();
+        assert_eq!(json, "[1,2]");
+        assert_eq!(
+            serde_json::from_str::<Secret<Vec<u8>>>(&json).unwrap(),
+            secret
+        );
+    }
+}
@@ -5,12 +5,54 @@ pub struct Args {
     pub action: Action,
 
     /// Load secret store from INPUT
+    ///
+    /// INPUT may be a file, a directory (an existing store, an empty directory, or a new path
+    /// ending in `/`), an S3 object as `s3://bucket/key`, or `-` for stdin
     #[clap(name = "INPUT")]
     pub store: Option<Source>,
 
-    /// Save the store to OUTPUT
-    #[clap(short, long, name = "OUTPUT")]
-    pub save: Option<Source>,
+    /// Save the store to OUTPUT, given as `--save=OUTPUT`, or `-` for stdout
+    ///
+    /// Without OUTPUT, saves back to INPUT with the same password. If missing, modifications are
+    /// written to stdout in the PIPE format. Existing S3 objects are only saved over when they are
+    /// also INPUT, so that concurrent saves never clobber each other
+    // `clap` maps the outer option to the flag and the inner one to its value
+    #[allow(clippy::option_option)]
+    #[clap(short, long, name = "OUTPUT", require_equals = true)]
+    pub save: Option<Option<Source>>,
+
+    /// Endpoint URL for S3 sources, such as a local `MinIO` server
+    #[clap(long, env = "PASSIFIER_S3_ENDPOINT")]
+    pub s3_endpoint: Option<String>,
+
+    /// Format used when piping the modified store to stdout [binary, json, pretty]
+    #[clap(short, long, name = "PIPE", default_value = "binary", parse(try_from_str = parse_format))]
+    pub pipe: Format,
+
+    /// Compression used when saving [none, deflate, zstd]
+    #[clap(short, long, parse(try_from_str = parse_compression))]
+    pub compression: Option<store::Compression>,
+
+    /// Cipher used when saving [aes256gcm, xchacha20poly1305], keeping the current one by default
+    #[clap(long, parse(try_from_str = parse_cipher))]
+    pub cipher: Option<store::Cipher>,
+
+    /// Unlock the store with the key in FILE instead of a password, without prompting
+    #[clap(long, value_name = "FILE", env = "PASSIFIER_KEY_FILE")]
+    pub key_file: Option<std::path::PathBuf>,
+
+    /// Prompt for the password as well, when the store requires both it and the key file
+    #[clap(long, requires = "key-file")]
+    pub with_password: bool,
+
+    /// Unlock a store encrypted to recipients with the identity in FILE, as made by `keygen`
+    #[clap(
+        long,
+        value_name = "FILE",
+        env = "PASSIFIER_IDENTITY",
+        conflicts_with_all = &["key-file", "with-password"]
+    )]
+    pub identity: Option<std::path::PathBuf>,
 }
 
 #[derive(clap::Clap, Debug)]
@@ -21,14 +63,100 @@ pub enum Action {
     /// Create new secret
     Create(Entry),
 
-    /// Read an existing secret
-    Read(Path),
+    /// Read an existing secret, recording when it was read if saving with `--save`
+    Read(Read),
 
     /// Update an existing secret
     Update(Entry),
 
     /// Delete an existing secret
     Delete(Path),
+
+    /// List the names and kinds of the secrets under PATH, without their values
+    List(List),
+
+    /// Show the secrets under PATH as a tree of names and kinds, without their values
+    Tree(Tree),
+
+    /// Show the metadata of a secret, without its value
+    ///
+    /// Giving any of the options edits the metadata instead, modifying the store
+    Info(Info),
+
+    /// Show the previous values kept for a secret, most recent first
+    History(History),
+
+    /// Restore a previous value of a secret, keeping the current one in its history
+    Rollback(Rollback),
+
+    /// Drop the previous values of the secrets under PATH
+    PruneHistory(PruneHistory),
+
+    /// Show or set how many previous values are kept for each secret
+    Retention(Retention),
+
+    /// Change the password or key file of the store, encrypting it again in the current format
+    ///
+    /// The store gets a new data key, so shares from `split` no longer apply. Saves to OUTPUT if
+    /// given, or back to INPUT otherwise
+    Rekey(Rekey),
+
+    /// Generate an identity to encrypt stores to, printing its public key
+    Keygen(Keygen),
+
+    /// Show or change the public keys the store is encrypted to
+    ///
+    /// Saves to OUTPUT if given, or back to INPUT otherwise
+    Recipients(Recipients),
+
+    /// Split the data key of the store into shares, printing one per line
+    ///
+    /// Any THRESHOLD of the shares recover the store with `recover`, even without its password or
+    /// identity. Saves to OUTPUT if given, or back to INPUT otherwise
+    Split(Split),
+
+    /// Recover the store from shares read on stdin, one per line, and set a new password
+    ///
+    /// Saves to OUTPUT if given, or back to INPUT otherwise
+    Recover(Rekey),
+
+    /// Open an interactive shell, decrypting the store only once
+    ///
+    /// The shell saves to OUTPUT if given, or back to INPUT otherwise
+    Shell,
+}
+
+impl Action {
+    /// Whether the action reads a secret from stdin
+    pub fn reads_stdin(&self) -> bool {
+        match self {
+            Self::Create(entry) | Self::Update(entry) => entry.input.stdin,
+            Self::Recover(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the action is about how the store is encrypted, rather than about its secrets
+    pub fn handles_encryption(&self) -> bool {
+        matches!(
+            self,
+            Self::Rekey(_) | Self::Recipients(_) | Self::Split(_) | Self::Recover(_)
+        )
+    }
+
+    /// Whether the action modifies the store
+    pub fn modifies(&self) -> bool {
+        match self {
+            Self::Create(_)
+            | Self::Update(_)
+            | Self::Delete(_)
+            | Self::Rollback(_)
+            | Self::PruneHistory(_) => true,
+            Self::Info(info) => info.edits(),
+            Self::Retention(retention) => retention.count.is_some(),
+            _ => false,
+        }
+    }
 }
 
 #[derive(clap::Clap, Debug)]
@@ -40,85 +168,393 @@ pub struct Print {
 
 #[derive(clap::Clap, Debug)]
 pub struct Path {
-    /// Path to the secret
-    pub path: Entries,
+    /// Path to the secret, as `hosts."example.com".password` or `/hosts/example.com/password`
+    pub path: store::Path,
 }
 
 #[derive(clap::Clap, Debug)]
-pub struct Entry {
-    /// Path to the secret
-    pub path: Entries,
+pub struct Read {
+    /// Path to the secret, as `hosts."example.com".password` or `/hosts/example.com/password`
+    pub path: store::Path,
+
+    /// Format of the value [raw, json, base64, hex, env] [default: json]
+    #[clap(short, long, parse(try_from_str = parse_value_format))]
+    pub format: Option<ValueFormat>,
+
+    /// Print the value as is, without quotes or a trailing line break, same as `--format raw`
+    #[clap(long, conflicts_with = "format")]
+    pub raw: bool,
 
-    /// Value for the secret
-    #[clap( parse(try_from_str = parse_entry))]
-    pub secret: store::Entry,
+    /// Write the value to FILE instead, readable only by its owner
+    #[clap(short, long, value_name = "FILE")]
+    pub output: Option<std::path::PathBuf>,
 }
 
-#[derive(Debug)]
-pub struct Entries(Vec<String>);
+impl Read {
+    /// The format asked for, either way
+    pub fn format(&self) -> ValueFormat {
+        if self.raw {
+            ValueFormat::Raw
+        } else {
+            self.format.unwrap_or(ValueFormat::Json)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ValueFormat {
+    Raw,
+    Json,
+    Base64,
+    Hex,
+    Env,
+}
 
-impl AsRef<[String]> for Entries {
-    fn as_ref(&self) -> &[String] {
-        &self.0
+#[derive(clap::Clap, Debug)]
+pub struct List {
+    /// Path to a nested store, or the whole store if missing
+    pub path: Option<store::Path>,
+
+    /// Levels of nested stores to descend into
+    #[clap(short, long, default_value = "1")]
+    pub depth: usize,
+}
+
+#[derive(clap::Clap, Debug)]
+pub struct Tree {
+    /// Path to a nested store, or the whole store if missing
+    pub path: Option<store::Path>,
+
+    /// Levels of nested stores to descend into, or all of them if missing
+    #[clap(short, long)]
+    pub depth: Option<usize>,
+}
+
+#[derive(clap::Clap, Debug)]
+pub struct Info {
+    /// Path to the secret, as `hosts."example.com".password` or `/hosts/example.com/password`
+    pub path: store::Path,
+
+    /// Set the notes, or clear them if empty
+    #[clap(long, setting = clap::ArgSettings::AllowEmptyValues)]
+    pub notes: Option<String>,
+
+    /// Set the URL, or clear it if empty
+    #[clap(long, setting = clap::ArgSettings::AllowEmptyValues)]
+    pub url: Option<String>,
+
+    /// Add a tag, may be repeated
+    #[clap(long = "tag", name = "TAG", number_of_values = 1)]
+    pub tags: Vec<String>,
+
+    /// Remove a tag, may be repeated
+    #[clap(long = "untag", name = "UNTAG", number_of_values = 1)]
+    pub untags: Vec<String>,
+}
+
+impl Info {
+    /// Whether any of the metadata is being edited
+    pub fn edits(&self) -> bool {
+        self.notes.is_some()
+            || self.url.is_some()
+            || !self.tags.is_empty()
+            || !self.untags.is_empty()
     }
+
+    /// Applies the edits to `metadata`
+    pub fn apply(&self, metadata: &mut store::Metadata) {
+        let non_empty = |value: &String| Some(value.clone()).filter(|value| !value.is_empty());
+
+        if let Some(notes) = &self.notes {
+            metadata.notes = non_empty(notes);
+        }
+        if let Some(url) = &self.url {
+            metadata.url = non_empty(url);
+        }
+        metadata.tags.extend(self.tags.iter().cloned());
+        for tag in &self.untags {
+            metadata.tags.remove(tag);
+        }
+    }
+}
+
+#[derive(clap::Clap, Debug)]
+pub struct History {
+    /// Path to the secret, as `hosts."example.com".password` or `/hosts/example.com/password`
+    pub path: store::Path,
+
+    /// Also show the previous values
+    #[clap(short, long)]
+    pub values: bool,
+}
+
+#[derive(clap::Clap, Debug)]
+pub struct Rollback {
+    /// Path to the secret, as `hosts."example.com".password` or `/hosts/example.com/password`
+    pub path: store::Path,
+
+    /// Version to restore, as numbered by `history`
+    #[clap(long)]
+    pub to: usize,
 }
 
-fn parse_entry(string: &str) -> anyhow::Result<store::Entry> {
-    fn remove_empties(entry: &mut store::Entry) -> bool {
-        if let store::Entry::Nested(nested) = entry {
-            let secrets = nested.secrets().map(String::from).collect::<Vec<_>>();
-            for secret in secrets {
-                if remove_empties(nested.get(&secret).unwrap()) {
-                    nested.delete(&secret).unwrap();
-                }
+#[derive(clap::Clap, Debug)]
+pub struct PruneHistory {
+    /// Path to a secret or nested store, or the whole store if missing
+    pub path: Option<store::Path>,
+
+    /// Previous values to keep for each secret, or the retention of the store if missing
+    #[clap(short, long)]
+    pub keep: Option<usize>,
+}
+
+#[derive(clap::Clap, Debug)]
+pub struct Retention {
+    /// Previous values to keep for each secret
+    pub count: Option<usize>,
+}
+
+#[derive(clap::Clap, Debug)]
+pub struct Rekey {
+    /// Unlock the store with the key in FILE from now on, instead of a password
+    #[clap(long, value_name = "FILE")]
+    pub key_file: Option<std::path::PathBuf>,
+
+    /// Prompt for a new password as well, requiring both it and the key file from now on
+    #[clap(long, requires = "key-file")]
+    pub with_password: bool,
+}
+
+#[derive(clap::Clap, Debug)]
+pub struct Split {
+    /// How many shares to print
+    #[clap(short = 'n', long, value_name = "COUNT")]
+    pub shares: u8,
+
+    /// How many shares are needed to recover the store
+    #[clap(short = 'k', long, value_name = "COUNT")]
+    pub threshold: u8,
+}
+
+#[derive(clap::Clap, Debug)]
+pub struct Keygen {
+    /// File to write the identity to, which must not exist yet
+    #[clap(short, long, value_name = "FILE")]
+    pub output: std::path::PathBuf,
+}
+
+#[derive(clap::Clap, Debug)]
+pub struct Recipients {
+    #[clap(subcommand)]
+    pub action: RecipientsAction,
+}
+
+#[derive(clap::Clap, Debug)]
+pub enum RecipientsAction {
+    /// List the public keys the store is encrypted to
+    List,
+
+    /// Encrypt the store to more public keys
+    ///
+    /// A store that was encrypted with a password is then encrypted to the public keys only
+    Add(RecipientList),
+
+    /// Stop encrypting the store to some public keys
+    ///
+    /// The store gets a new data key, so shares from `split` no longer apply
+    Remove(RecipientList),
+}
+
+#[derive(clap::Clap, Debug)]
+pub struct RecipientList {
+    /// Public keys, as printed by `keygen`
+    #[clap(name = "RECIPIENT", required = true)]
+    pub recipients: Vec<store::Recipient>,
+}
+
+#[derive(clap::Clap, Debug)]
+pub struct Entry {
+    /// Path to the secret, as `hosts."example.com".password` or `/hosts/example.com/password`
+    pub path: store::Path,
+
+    /// Value for the secret, which is visible to other processes, unless read some other way
+    #[clap(
+        parse(try_from_str = parse_entry),
+        required_unless_present_any = &["generate", "stdin", "file", "prompt"]
+    )]
+    pub secret: Option<store::Entry>,
+
+    /// Generate a random value instead, which is not shown unless `--show` is given
+    #[clap(short, long, conflicts_with_all = &["secret", "stdin", "file", "prompt"])]
+    pub generate: bool,
+
+    #[clap(flatten)]
+    pub options: Generate,
+
+    #[clap(flatten)]
+    pub input: Input,
+}
+
+#[derive(clap::Clap, Debug)]
+pub struct Input {
+    /// Read the value from stdin, without its trailing line break
+    #[clap(long, conflicts_with_all = &["secret", "file", "prompt"])]
+    pub stdin: bool,
+
+    /// Read the value from FILE, as is
+    #[clap(long, value_name = "FILE", conflicts_with_all = &["secret", "prompt"])]
+    pub file: Option<std::path::PathBuf>,
+
+    /// Prompt for the value without echoing it, asking twice to confirm
+    #[clap(long, conflicts_with = "secret")]
+    pub prompt: bool,
+
+    /// Store the value read from stdin or a file as [string, binary], instead of as a string
+    /// only when it is valid UTF-8
+    #[clap(long = "as", value_name = "KIND", parse(try_from_str = parse_kind))]
+    pub kind: Option<Kind>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Kind {
+    String,
+    Binary,
+}
+
+#[derive(clap::Clap, Debug)]
+pub struct Generate {
+    /// Length of the generated value [default: 24 characters, or 7 words]
+    #[clap(long, requires = "generate")]
+    pub length: Option<usize>,
+
+    /// Character classes to draw from [lower, upper, digits, symbols], separated by commas
+    #[clap(
+        long,
+        requires = "generate",
+        require_delimiter = true,
+        parse(try_from_str = parse_class)
+    )]
+    pub classes: Vec<super::generate::Class>,
+
+    /// Leave out characters that are easily mistaken for one another, such as `0` and `O`
+    #[clap(long, requires = "generate")]
+    pub no_ambiguous: bool,
+
+    /// Generate a passphrase of words from the bundled wordlist instead of characters
+    #[clap(long, requires = "generate")]
+    pub words: bool,
+
+    /// Separator between the words of a passphrase [default: -]
+    #[clap(long, requires = "words")]
+    pub separator: Option<String>,
+
+    /// Print the generated value to stderr
+    #[clap(long, requires = "generate")]
+    pub show: bool,
+}
+
+impl Generate {
+    pub fn generator(&self) -> super::generate::Generator {
+        if self.words {
+            super::generate::Generator::Words {
+                count: self.length.unwrap_or(7),
+                separator: self.separator.clone().unwrap_or_else(|| String::from("-")),
             }
-            nested.secrets().next().is_none()
         } else {
-            false
+            super::generate::Generator::Characters {
+                length: self.length.unwrap_or(24),
+                classes: if self.classes.is_empty() {
+                    super::generate::Class::ALL.to_vec()
+                } else {
+                    self.classes.clone()
+                },
+                exclude_ambiguous: self.no_ambiguous,
+            }
         }
     }
+}
 
+pub fn parse_entry(string: &str) -> anyhow::Result<store::Entry> {
     if string
         .split_whitespace()
         .next()
         .map(|s| s.starts_with('{') || s.starts_with('[') || s.starts_with('"'))
         .ok_or_else(|| anyhow::anyhow!("Empty secret"))?
     {
-        let mut entry = serde_json::from_str(string)?;
-        remove_empties(&mut entry);
-        Ok(entry)
+        Ok(serde_json::from_str(string)?)
     } else {
-        Ok(store::Entry::String(String::from(string)))
+        Ok(store::Entry::String(String::from(string).into()))
     }
 }
 
-impl std::str::FromStr for Entries {
-    type Err = anyhow::Error;
+fn parse_format(string: &str) -> anyhow::Result<Format> {
+    match string.trim().to_lowercase().as_str() {
+        "binary" => Ok(Format::Binary),
+        "json" => Ok(Format::Json),
+        "pretty" => Ok(Format::Pretty),
+        _ => Err(anyhow::anyhow!("Unknown format: {}", string)),
+    }
+}
 
-    fn from_str(string: &str) -> Result<Self, Self::Err> {
-        let entries = string
-            .split('.')
-            .filter_map(|s| {
-                let trimmed = s.trim();
-                if trimmed.is_empty() {
-                    None
-                } else {
-                    Some(String::from(trimmed))
-                }
-            })
-            .collect::<Vec<_>>();
-        if entries.is_empty() {
-            Err(anyhow::anyhow!("Empty path"))
-        } else {
-            Ok(Self(entries))
-        }
+fn parse_value_format(string: &str) -> anyhow::Result<ValueFormat> {
+    match string.trim().to_lowercase().as_str() {
+        "raw" => Ok(ValueFormat::Raw),
+        "json" => Ok(ValueFormat::Json),
+        "base64" => Ok(ValueFormat::Base64),
+        "hex" => Ok(ValueFormat::Hex),
+        "env" => Ok(ValueFormat::Env),
+        _ => Err(anyhow::anyhow!("Unknown format: {}", string)),
+    }
+}
+
+fn parse_kind(string: &str) -> anyhow::Result<Kind> {
+    match string.trim().to_lowercase().as_str() {
+        "string" => Ok(Kind::String),
+        "binary" => Ok(Kind::Binary),
+        _ => Err(anyhow::anyhow!("Unknown kind: {}", string)),
+    }
+}
+
+fn parse_class(string: &str) -> anyhow::Result<super::generate::Class> {
+    match string.trim().to_lowercase().as_str() {
+        "lower" => Ok(super::generate::Class::Lower),
+        "upper" => Ok(super::generate::Class::Upper),
+        "digits" => Ok(super::generate::Class::Digits),
+        "symbols" => Ok(super::generate::Class::Symbols),
+        _ => Err(anyhow::anyhow!("Unknown character class: {}", string)),
     }
 }
 
-#[derive(Debug)]
+fn parse_compression(string: &str) -> anyhow::Result<store::Compression> {
+    match string.trim().to_lowercase().as_str() {
+        "none" => Ok(store::Compression::None),
+        "deflate" => Ok(store::Compression::Deflate),
+        "zstd" => Ok(store::Compression::Zstd),
+        _ => Err(anyhow::anyhow!("Unknown compression: {}", string)),
+    }
+}
+
+fn parse_cipher(string: &str) -> anyhow::Result<store::Cipher> {
+    match string.trim().to_lowercase().as_str() {
+        "aes256gcm" | "aes-256-gcm" => Ok(store::Cipher::Aes256Gcm),
+        "xchacha20poly1305" | "xchacha20-poly1305" => Ok(store::Cipher::XChaCha20Poly1305),
+        _ => Err(anyhow::anyhow!("Unknown cipher: {}", string)),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Format {
+    Binary,
+    Json,
+    Pretty,
+}
+
+#[derive(Debug, PartialEq, Eq)]
 pub enum Source {
+    Pipe,
     File(std::path::PathBuf),
-    S3(String),
+    Directory(std::path::PathBuf),
+    S3 { bucket: String, key: String },
 }
 
 impl std::str::FromStr for Source {
@@ -126,14 +562,28 @@ impl std::str::FromStr for Source {
 
     fn from_str(string: &str) -> Result<Self, Self::Err> {
         let trimmed = string.trim();
-        if let Some(path) = trimmed.strip_prefix("s3://") {
-            if path.is_empty() {
-                Err(anyhow::anyhow!("Invalid S3 path"))
-            } else {
-                Ok(Self::S3(String::from(path)))
+        if trimmed == "-" {
+            Ok(Self::Pipe)
+        } else if let Some(path) = trimmed.strip_prefix("s3://") {
+            match path.split_once('/') {
+                Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => Ok(Self::S3 {
+                    bucket: String::from(bucket),
+                    key: String::from(key),
+                }),
+                _ => Err(anyhow::anyhow!("Invalid S3 path, expected s3://bucket/key")),
             }
         } else {
-            Ok(Self::File(std::path::PathBuf::from_str(string)?))
+            let path = std::path::PathBuf::from_str(string)?;
+            if path.is_dir() && !super::directory::is_store(&path) {
+                Err(anyhow::anyhow!(
+                    "{} is a directory, but neither empty nor a store",
+                    path.display()
+                ))
+            } else if string.ends_with(std::path::is_separator) || path.is_dir() {
+                Ok(Self::Directory(path))
+            } else {
+                Ok(Self::File(path))
+            }
         }
     }
 }
@@ -144,22 +594,22 @@ mod tests {
     fn parse_entry() {
         assert_eq!(
             super::parse_entry("foobar").unwrap(),
-            store::Entry::String(String::from("foobar"))
+            store::Entry::String(String::from("foobar").into())
         );
 
         assert_eq!(
             super::parse_entry("\"foobar\"").unwrap(),
-            store::Entry::String(String::from("foobar"))
+            store::Entry::String(String::from("foobar").into())
         );
 
         assert_eq!(
             super::parse_entry(" \n\t\r \"foobar\"").unwrap(),
-            store::Entry::String(String::from("foobar"))
+            store::Entry::String(String::from("foobar").into())
         );
 
         assert_eq!(
             super::parse_entry("[1, 2]").unwrap(),
-            store::Entry::Binary(vec![1, 2])
+            store::Entry::Binary(vec![1, 2].into())
         );
 
         assert_eq!(
@@ -169,7 +619,433 @@ mod tests {
 
         assert_eq!(
             super::parse_entry(r#"{"nested":{"inner":{}}}"#).unwrap(),
-            store::Entry::Nested(store::Store::new())
+            store::Entry::Nested(serde_json::from_str(r#"{"nested":{"inner":{}}}"#).unwrap())
+        );
+    }
+
+    #[test]
+    fn parse_source() {
+        use super::Source;
+
+        assert_eq!(
+            "s3://bucket/path/to/store".parse::<Source>().unwrap(),
+            Source::S3 {
+                bucket: String::from("bucket"),
+                key: String::from("path/to/store")
+            }
+        );
+
+        assert_eq!(
+            "path/to/store".parse::<Source>().unwrap(),
+            Source::File(std::path::PathBuf::from("path/to/store"))
+        );
+
+        assert_eq!(
+            "path/to/store/".parse::<Source>().unwrap(),
+            Source::Directory(std::path::PathBuf::from("path/to/store/"))
+        );
+
+        // Only empty directories and stores, never any other directory
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().to_str().unwrap();
+        assert_eq!(
+            path.parse::<Source>().unwrap(),
+            Source::Directory(std::path::PathBuf::from(path))
+        );
+        std::fs::write(directory.path().join("unrelated"), b"").unwrap();
+        assert!(path.parse::<Source>().is_err());
+        assert!(format!("{path}/").parse::<Source>().is_err());
+        std::fs::write(directory.path().join(".passifier"), b"").unwrap();
+        assert_eq!(
+            path.parse::<Source>().unwrap(),
+            Source::Directory(std::path::PathBuf::from(path))
+        );
+        assert!(".".parse::<Source>().is_err());
+
+        assert_eq!("-".parse::<Source>().unwrap(), Source::Pipe);
+
+        assert!("s3://".parse::<Source>().is_err());
+        assert!("s3://bucket".parse::<Source>().is_err());
+        assert!("s3://bucket/".parse::<Source>().is_err());
+        assert!("s3:///key".parse::<Source>().is_err());
+    }
+
+    #[test]
+    fn parse_save() {
+        use clap::Clap;
+
+        let save = |argv: &[&str]| super::Args::try_parse_from(argv).unwrap().save;
+        let file = |path: &str| super::Source::File(std::path::PathBuf::from(path));
+
+        assert_eq!(save(&["passifier", "input", "print"]), None);
+        assert_eq!(save(&["passifier", "input", "-s", "print"]), Some(None));
+        assert_eq!(save(&["passifier", "-s", "input", "print"]), Some(None));
+        assert_eq!(
+            save(&["passifier", "input", "--save=output", "print"]),
+            Some(Some(file("output")))
+        );
+        assert_eq!(
+            save(&["passifier", "input", "-s=output", "print"]),
+            Some(Some(file("output")))
+        );
+    }
+
+    #[test]
+    fn parse_read() {
+        use clap::Clap;
+
+        let parse = |argv: &[&str]| match super::Args::try_parse_from(argv)?.action {
+            super::Action::Read(read) => Ok::<_, clap::Error>(read),
+            action => panic!("Unexpected action: {:?}", action),
+        };
+
+        let read = parse(&["passifier", "input", "read", "key"]).unwrap();
+        assert_eq!(read.format(), super::ValueFormat::Json);
+        assert_eq!(read.output, None);
+
+        let read = parse(&["passifier", "input", "read", "key", "--raw"]).unwrap();
+        assert_eq!(read.format(), super::ValueFormat::Raw);
+
+        let read = parse(&[
+            "passifier",
+            "input",
+            "read",
+            "key",
+            "-f",
+            "hex",
+            "-o",
+            "key.bin",
+        ])
+        .unwrap();
+        assert_eq!(read.format(), super::ValueFormat::Hex);
+        assert_eq!(read.output, Some(std::path::PathBuf::from("key.bin")));
+
+        assert!(parse(&["passifier", "input", "read", "key", "--raw", "-f", "env"]).is_err());
+        assert!(parse(&["passifier", "input", "read", "key", "-f", "yaml"]).is_err());
+    }
+
+    #[test]
+    fn parse_key_file() {
+        use clap::Clap;
+
+        let parse = |argv: &[&str]| super::Args::try_parse_from(argv);
+
+        let args = parse(&["passifier", "--key-file", "key", "input", "print"]).unwrap();
+        assert_eq!(args.key_file, Some(std::path::PathBuf::from("key")));
+        assert!(!args.with_password);
+        assert!(parse(&["passifier", "--with-password", "input", "print"]).is_err());
+
+        match parse(&[
+            "passifier",
+            "input",
+            "rekey",
+            "--key-file",
+            "new",
+            "--with-password",
+        ])
+        .unwrap()
+        .action
+        {
+            super::Action::Rekey(rekey) => {
+                assert_eq!(rekey.key_file, Some(std::path::PathBuf::from("new")));
+                assert!(rekey.with_password);
+            }
+            action => panic!("Unexpected action: {:?}", action),
+        }
+        assert!(parse(&["passifier", "input", "rekey", "--with-password"]).is_err());
+    }
+
+    #[test]
+    fn parse_recipients() {
+        use clap::Clap;
+
+        let recipient = store::Identity::generate().recipient();
+        let parse = |argv: &[&str]| match super::Args::try_parse_from(argv)?.action {
+            super::Action::Recipients(recipients) => Ok::<_, clap::Error>(recipients.action),
+            action => panic!("Unexpected action: {:?}", action),
+        };
+
+        assert!(matches!(
+            parse(&["passifier", "input", "recipients", "list"]).unwrap(),
+            super::RecipientsAction::List
+        ));
+        match parse(&[
+            "passifier",
+            "input",
+            "recipients",
+            "add",
+            &recipient.to_string(),
+        ])
+        .unwrap()
+        {
+            super::RecipientsAction::Add(add) => assert_eq!(add.recipients, [recipient]),
+            action => panic!("Unexpected action: {:?}", action),
+        }
+        assert!(parse(&["passifier", "input", "recipients", "remove"]).is_err());
+        assert!(parse(&["passifier", "input", "recipients", "add", "psfr-pk-00"]).is_err());
+
+        assert!(super::Args::try_parse_from([
+            "passifier",
+            "--identity",
+            "id",
+            "--key-file",
+            "key",
+            "input",
+            "print"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn parse_split() {
+        use clap::Clap;
+
+        let parse = |argv: &[&str]| super::Args::try_parse_from(argv).map(|args| args.action);
+
+        match parse(&["passifier", "input", "split", "-n", "5", "--threshold", "3"]).unwrap() {
+            super::Action::Split(split) => {
+                assert_eq!(split.shares, 5);
+                assert_eq!(split.threshold, 3);
+            }
+            action => panic!("Unexpected action: {:?}", action),
+        }
+        assert!(parse(&["passifier", "input", "split", "-n", "5"]).is_err());
+        assert!(parse(&["passifier", "input", "split", "-n", "256", "-k", "2"]).is_err());
+
+        let recover = parse(&["passifier", "input", "recover", "--key-file", "key"]).unwrap();
+        assert!(recover.reads_stdin());
+        assert!(recover.handles_encryption());
+    }
+
+    #[test]
+    fn parse_listing() {
+        use clap::Clap;
+
+        let parse = |argv: &[&str]| super::Args::try_parse_from(argv).unwrap().action;
+
+        match parse(&["passifier", "input", "list"]) {
+            super::Action::List(list) => {
+                assert_eq!(list.path, None);
+                assert_eq!(list.depth, 1);
+            }
+            action => panic!("Unexpected action: {:?}", action),
+        }
+
+        match parse(&["passifier", "input", "tree", "hosts", "-d", "2"]) {
+            super::Action::Tree(tree) => {
+                assert_eq!(tree.path, Some("hosts".parse().unwrap()));
+                assert_eq!(tree.depth, Some(2));
+            }
+            action => panic!("Unexpected action: {:?}", action),
+        }
+    }
+
+    #[test]
+    fn parse_info() {
+        use clap::Clap;
+
+        let info = |argv: &[&str]| match super::Args::try_parse_from(argv).unwrap().action {
+            super::Action::Info(info) => info,
+            action => panic!("Unexpected action: {:?}", action),
+        };
+
+        let plain = info(&["passifier", "input", "info", "hosts.mail"]);
+        assert!(!plain.edits());
+
+        let edit = info(&[
+            "passifier",
+            "input",
+            "info",
+            "--tag",
+            "work",
+            "--tag",
+            "mail",
+            "hosts.mail",
+            "--notes",
+            "",
+        ]);
+        assert!(edit.edits());
+        assert_eq!(edit.path, "hosts.mail".parse().unwrap());
+        assert_eq!(edit.tags, ["work", "mail"]);
+
+        let mut metadata = store::Metadata {
+            notes: Some(String::from("old")),
+            ..store::Metadata::default()
+        };
+        metadata.tags.insert(String::from("old"));
+        edit.apply(&mut metadata);
+        assert_eq!(metadata.notes, None);
+        assert_eq!(
+            metadata.tags.into_iter().collect::<Vec<_>>(),
+            ["mail", "old", "work"]
+        );
+    }
+
+    #[test]
+    fn parse_history() {
+        use clap::Clap;
+
+        let parse = |argv: &[&str]| super::Args::try_parse_from(argv).unwrap().action;
+
+        match parse(&["passifier", "input", "rollback", "hosts.mail", "--to", "2"]) {
+            super::Action::Rollback(rollback) => {
+                assert_eq!(rollback.path, "hosts.mail".parse().unwrap());
+                assert_eq!(rollback.to, 2);
+            }
+            action => panic!("Unexpected action: {:?}", action),
+        }
+
+        match parse(&["passifier", "input", "prune-history", "--keep", "0"]) {
+            super::Action::PruneHistory(prune) => {
+                assert_eq!(prune.path, None);
+                assert_eq!(prune.keep, Some(0));
+            }
+            action => panic!("Unexpected action: {:?}", action),
+        }
+
+        assert!(super::Args::try_parse_from(["passifier", "input", "rollback", "hosts"]).is_err());
+    }
+
+    #[test]
+    fn parse_generate() {
+        use crate::generate::{Class, Generator};
+        use clap::Clap;
+
+        let entry = |argv: &[&str]| match super::Args::try_parse_from(argv).map(|args| args.action)
+        {
+            Ok(super::Action::Create(entry)) => Ok(entry),
+            Ok(action) => panic!("Unexpected action: {:?}", action),
+            Err(error) => Err(error),
+        };
+
+        let plain = entry(&["passifier", "create", "path", "value"]).unwrap();
+        assert_eq!(
+            plain.secret,
+            Some(store::Entry::String(String::from("value").into()))
+        );
+        assert!(!plain.generate);
+
+        let generated = entry(&[
+            "passifier",
+            "create",
+            "path",
+            "--generate",
+            "--length",
+            "12",
+            "--classes",
+            "lower,digits",
+            "--no-ambiguous",
+        ])
+        .unwrap();
+        assert_eq!(generated.secret, None);
+        assert_eq!(
+            generated.options.generator(),
+            Generator::Characters {
+                length: 12,
+                classes: vec![Class::Lower, Class::Digits],
+                exclude_ambiguous: true,
+            }
+        );
+
+        let words = entry(&["passifier", "create", "path", "-g", "--words"]).unwrap();
+        assert_eq!(
+            words.options.generator(),
+            Generator::Words {
+                count: 7,
+                separator: String::from("-"),
+            }
+        );
+
+        assert!(entry(&["passifier", "create", "path"]).is_err());
+        assert!(entry(&["passifier", "create", "path", "value", "--generate"]).is_err());
+        assert!(entry(&["passifier", "create", "path", "value", "--length", "3"]).is_err());
+        assert!(entry(&["passifier", "create", "path", "-g", "--classes", "emoji"]).is_err());
+    }
+
+    #[test]
+    fn parse_input() {
+        use clap::Clap;
+
+        let entry = |argv: &[&str]| match super::Args::try_parse_from(argv).map(|args| args.action)
+        {
+            Ok(super::Action::Update(entry)) => Ok(entry),
+            Ok(action) => panic!("Unexpected action: {:?}", action),
+            Err(error) => Err(error),
+        };
+
+        let stdin = entry(&["passifier", "update", "path", "--stdin", "--as", "binary"]).unwrap();
+        assert!(stdin.input.stdin);
+        assert_eq!(stdin.input.kind, Some(super::Kind::Binary));
+        assert_eq!(stdin.secret, None);
+
+        let file = entry(&["passifier", "update", "path", "--file", "key.pem"]).unwrap();
+        assert_eq!(file.input.file, Some(std::path::PathBuf::from("key.pem")));
+
+        assert!(entry(&["passifier", "update", "path", "--prompt"]).is_ok());
+        assert!(entry(&["passifier", "update", "path", "value", "--stdin"]).is_err());
+        assert!(entry(&["passifier", "update", "path", "--stdin", "--prompt"]).is_err());
+        assert!(entry(&["passifier", "update", "path", "--file", "a", "-g"]).is_err());
+        assert!(entry(&["passifier", "update", "path", "--stdin", "--as", "text"]).is_err());
+    }
+
+    #[test]
+    fn parse_format() {
+        assert_eq!(
+            super::parse_format("binary").unwrap(),
+            super::Format::Binary
+        );
+        assert_eq!(super::parse_format("JSON").unwrap(), super::Format::Json);
+        assert_eq!(
+            super::parse_format(" pretty ").unwrap(),
+            super::Format::Pretty
+        );
+        assert!(super::parse_format("yaml").is_err());
+    }
+
+    #[test]
+    fn parse_value_format() {
+        assert_eq!(
+            super::parse_value_format("raw").unwrap(),
+            super::ValueFormat::Raw
+        );
+        assert_eq!(
+            super::parse_value_format("Base64").unwrap(),
+            super::ValueFormat::Base64
+        );
+        assert_eq!(
+            super::parse_value_format(" env ").unwrap(),
+            super::ValueFormat::Env
+        );
+        assert!(super::parse_value_format("binary").is_err());
+    }
+
+    #[test]
+    fn parse_compression() {
+        assert_eq!(
+            super::parse_compression("none").unwrap(),
+            store::Compression::None
+        );
+        assert_eq!(
+            super::parse_compression("Deflate").unwrap(),
+            store::Compression::Deflate
+        );
+        assert_eq!(
+            super::parse_compression(" zstd ").unwrap(),
+            store::Compression::Zstd
+        );
+        assert!(super::parse_compression("gzip").is_err());
+    }
+
+    #[test]
+    fn parse_cipher() {
+        assert_eq!(
+            super::parse_cipher("AES256GCM").unwrap(),
+            store::Cipher::Aes256Gcm
+        );
+        assert_eq!(
+            super::parse_cipher(" xchacha20-poly1305 ").unwrap(),
+            store::Cipher::XChaCha20Poly1305
         );
+        assert!(super::parse_cipher("chacha20").is_err());
     }
 }
@@ -0,0 +1,701 @@
+//! Stores a secret store as a filesystem tree
+//!
+//! Every nested store is a directory and every other entry is a file holding that single entry,
+//! encrypted on its own. Names are percent-encoded so that any secret name maps to a valid file
+//! name on any platform. Names that only differ by case would share a file on case-insensitive
+//! filesystems, so such stores are refused. Files starting with a dot, such as `.git`, are never
+//! touched
+//!
+//! The root of the store holds a `.passifier` file, so that only new or empty directories and
+//! earlier stores are ever written to. Entries no longer in the store are only removed if
+//! they decrypt as secrets of the store, so that unrelated files are never deleted
+//!
+//! Files keep the metadata and previous values of their secret alongside its value. Nested stores
+//! have no file of their own, so their metadata is not kept. The store settings are encrypted into
+//! the `.passifier` file, which is left empty while they are the defaults
+
+/// Marks the root of a store
+pub const MARKER: &str = ".passifier";
+
+/// Whether `path` is a directory that a store may be saved to, either empty or marked as a store
+pub fn is_store(path: &std::path::Path) -> bool {
+    path.join(MARKER).is_file()
+        || std::fs::read_dir(path).is_ok_and(|mut items| items.next().is_none())
+}
+
+/// Loads a store from the directory at `path`
+///
+/// Files that are not secrets of the store are skipped with a warning, since saving leaves them
+/// in place. If there are only such files, the password is more likely wrong, which is an error
+pub fn load(path: &std::path::Path, crypter: &store::Crypter) -> anyhow::Result<store::Store> {
+    let mut skipped = Vec::new();
+    let store = load_into(path, crypter, &mut skipped)?;
+
+    if store.is_empty() && !skipped.is_empty() {
+        return Err(skipped.swap_remove(0));
+    }
+    for error in skipped {
+        eprintln!("Skipping {error}");
+    }
+
+    let mut store = store;
+    *store.settings_mut() = read_settings(path, crypter)?;
+    Ok(store)
+}
+
+fn load_into(
+    path: &std::path::Path,
+    crypter: &store::Crypter,
+    skipped: &mut Vec<anyhow::Error>,
+) -> anyhow::Result<store::Store> {
+    let mut store = store::Store::new();
+
+    for item in std::fs::read_dir(path)? {
+        let item = item?;
+        let file_name = item.file_name();
+        if file_name.to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        let Some(name) = file_name.to_str().and_then(decode) else {
+            skipped.push(anyhow::anyhow!("{:?}: Invalid file name", item.path()));
+            continue;
+        };
+
+        let record = if item.file_type()?.is_dir() {
+            let nested = load_into(&item.path(), crypter, skipped)?;
+            if nested.secrets().next().is_none() {
+                continue;
+            }
+            store::Record {
+                entry: store::Entry::Nested(nested),
+                metadata: store::Metadata::default(),
+                history: Vec::new(),
+            }
+        } else {
+            let data = std::fs::read(item.path())?;
+            match crypter.decrypt::<store::Record>(&data) {
+                Ok(record) => record,
+                Err(error) => {
+                    skipped.push(anyhow::anyhow!("{:?}: {}", item.path(), error));
+                    continue;
+                }
+            }
+        };
+
+        store.create(name.clone(), record.entry)?;
+        if let Some(current) = store.metadata_mut(&name) {
+            *current = record.metadata;
+        }
+        if let Some(current) = store.history_mut(&name) {
+            *current = record.history;
+        }
+    }
+
+    Ok(store)
+}
+
+/// Saves the store into the directory at `path`, creating it if needed
+///
+/// Files whose secret did not change are left untouched, so that version control only shows the
+/// secrets that actually changed. Entries no longer in the store are removed
+pub fn save(
+    store: &store::Store,
+    path: &std::path::Path,
+    crypter: &store::Crypter,
+) -> anyhow::Result<()> {
+    check_names(store)?;
+    prepare(path)?;
+    write_settings(store, path, crypter, false)?;
+    write(store, path, crypter, false)
+}
+
+/// Same as [`save`], but writes every file again, even if its secret did not change
+///
+/// Files are replaced one at a time, so an interrupted rewrite leaves some of them as they were
+pub fn rewrite(
+    store: &store::Store,
+    path: &std::path::Path,
+    crypter: &store::Crypter,
+) -> anyhow::Result<()> {
+    check_names(store)?;
+    prepare(path)?;
+    write_settings(store, path, crypter, true)?;
+    write(store, path, crypter, true)
+}
+
+/// Refuses names that only differ by case once encoded, before anything is written
+fn check_names(store: &store::Store) -> anyhow::Result<()> {
+    let mut seen = std::collections::HashMap::new();
+    for (name, entry) in store.iter() {
+        if let Some(other) = seen.insert(encode(name).to_lowercase(), name) {
+            anyhow::bail!(
+                "Secrets {:?} and {:?} would share a file on case-insensitive filesystems",
+                other,
+                name
+            );
+        }
+        if let store::Entry::Nested(nested) = entry {
+            check_names(nested)?;
+        }
+    }
+    Ok(())
+}
+
+/// Creates the root of the store if needed, refusing directories that are not stores
+fn prepare(path: &std::path::Path) -> anyhow::Result<()> {
+    if path.exists() && !is_store(path) {
+        anyhow::bail!(
+            "{} is not an empty directory or a store, it must contain a {} file to be saved to",
+            path.display(),
+            MARKER
+        );
+    }
+
+    std::fs::create_dir_all(path)?;
+    Ok(())
+}
+
+/// Reads the store settings from the marker, where a missing or empty marker means the defaults
+fn read_settings(
+    path: &std::path::Path,
+    crypter: &store::Crypter,
+) -> anyhow::Result<store::Settings> {
+    let marker = path.join(MARKER);
+    let data = match std::fs::read(&marker) {
+        Ok(data) => data,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(error) => return Err(error.into()),
+    };
+    if data.is_empty() {
+        return Ok(store::Settings::default());
+    }
+
+    crypter
+        .decrypt(&data)
+        .map_err(|error| anyhow::anyhow!("{:?}: {}", marker, error))
+}
+
+/// Writes the store settings into the marker, unless they did not change
+fn write_settings(
+    store: &store::Store,
+    path: &std::path::Path,
+    crypter: &store::Crypter,
+    rewrite: bool,
+) -> anyhow::Result<()> {
+    let marker = path.join(MARKER);
+    let unchanged = match std::fs::read(&marker) {
+        Err(_) => false,
+        // An empty marker has nothing to encrypt again
+        Ok(data) if data.is_empty() => *store.settings() == store::Settings::default(),
+        Ok(_) => !rewrite && read_settings(path, crypter).ok().as_ref() == Some(store.settings()),
+    };
+
+    if !unchanged {
+        let data = if *store.settings() == store::Settings::default() {
+            Vec::new()
+        } else {
+            crypter.encrypt(store.settings())?
+        };
+        super::save_to_file(&data, &marker)?;
+    }
+    Ok(())
+}
+
+fn write(
+    store: &store::Store,
+    path: &std::path::Path,
+    crypter: &store::Crypter,
+    rewrite: bool,
+) -> anyhow::Result<()> {
+    std::fs::create_dir_all(path)?;
+
+    let mut expected = std::collections::HashSet::new();
+
+    for (name, entry) in store.iter() {
+        let file_name = encode(name);
+        let target = path.join(&file_name);
+        expected.insert(file_name);
+
+        if let store::Entry::Nested(nested) = entry {
+            if target.is_file() && !remove_leaf(&target, crypter)? {
+                anyhow::bail!("{} is not a secret of the store", target.display());
+            }
+            write(nested, &target, crypter, rewrite)?;
+        } else {
+            if target.is_dir() && !remove_tree(&target, crypter)? {
+                anyhow::bail!(
+                    "{} holds files that are not secrets of the store",
+                    target.display()
+                );
+            }
+
+            let record = store::Record {
+                entry: entry.clone(),
+                metadata: store.metadata(name).cloned().unwrap_or_default(),
+                history: store.history(name).map(<[_]>::to_vec).unwrap_or_default(),
+            };
+            let unchanged = !rewrite
+                && std::fs::read(&target)
+                    .ok()
+                    .and_then(|data| crypter.decrypt::<store::Record>(&data).ok())
+                    .is_some_and(|current| current == record);
+
+            if !unchanged {
+                super::save_to_file(&crypter.encrypt(&record)?, &target)?;
+            }
+        }
+    }
+
+    for item in std::fs::read_dir(path)? {
+        let item = item?;
+        let file_name = item.file_name();
+        let file_name = file_name.to_string_lossy();
+
+        if file_name.starts_with('.') || expected.contains(file_name.as_ref()) {
+            continue;
+        }
+
+        if item.file_type()?.is_dir() {
+            remove_tree(&item.path(), crypter)?;
+        } else {
+            remove_leaf(&item.path(), crypter)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the file at `path` if it is a secret of the store, returning whether it did
+fn remove_leaf(path: &std::path::Path, crypter: &store::Crypter) -> anyhow::Result<bool> {
+    let leaf = crypter
+        .decrypt::<store::Record>(&std::fs::read(path)?)
+        .is_ok();
+    if leaf {
+        std::fs::remove_file(path)?;
+    }
+    Ok(leaf)
+}
+
+/// Removes the secrets of the store under `path`, and then the directories left empty, returning
+/// whether `path` itself was removed
+fn remove_tree(path: &std::path::Path, crypter: &store::Crypter) -> anyhow::Result<bool> {
+    let mut empty = true;
+    for item in std::fs::read_dir(path)? {
+        let item = item?;
+        let removed = if item.file_name().to_string_lossy().starts_with('.') {
+            false
+        } else if item.file_type()?.is_dir() {
+            remove_tree(&item.path(), crypter)?
+        } else {
+            remove_leaf(&item.path(), crypter)?
+        };
+        empty &= removed;
+    }
+
+    if empty {
+        std::fs::remove_dir(path)?;
+    }
+    Ok(empty)
+}
+
+/// Device names reserved on Windows, regardless of extension
+const RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Percent-encodes a secret name into a portable file name
+///
+/// Only ASCII alphanumerics, `-`, `_`, `.`, and spaces are kept. Leading dots, trailing dots and
+/// spaces, and reserved device names are also encoded. The empty name is encoded as a lone `%`
+fn encode(name: &str) -> String {
+    if name.is_empty() {
+        return String::from("%");
+    }
+
+    let reserved = {
+        let stem = name.split('.').next().unwrap_or_default();
+        RESERVED
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    };
+    let last = name.len() - 1;
+
+    name.bytes()
+        .enumerate()
+        .map(|(index, byte)| {
+            let safe = matches!(byte, b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b' ')
+                && !(index == 0 && (byte == b'.' || reserved))
+                && !(index == last && (byte == b'.' || byte == b' '));
+
+            if safe {
+                String::from(char::from(byte))
+            } else {
+                format!("%{byte:02X}")
+            }
+        })
+        .collect()
+}
+
+/// Reverses [`encode`], rejecting anything `encode` would not have produced
+fn decode(file_name: &str) -> Option<String> {
+    use std::convert::TryFrom;
+
+    if file_name == "%" {
+        return Some(String::new());
+    }
+
+    let mut bytes = Vec::with_capacity(file_name.len());
+    let mut input = file_name.bytes();
+    while let Some(byte) = input.next() {
+        if byte == b'%' {
+            let high = char::from(input.next()?).to_digit(16)?;
+            let low = char::from(input.next()?).to_digit(16)?;
+            bytes.push(u8::try_from(high << 4 | low).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+
+    String::from_utf8(bytes)
+        .ok()
+        .filter(|name| !name.is_empty() && encode(name) == file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    fn crypter() -> store::Crypter {
+        store::Crypter::new("pass").with_kdf(store::Kdf::Pbkdf2 { rounds: 16 })
+    }
+
+    fn make_store() -> store::Store {
+        serde_json::from_str(
+            r#"{
+                 "binary": [ 245, 107, 95, 100 ],
+                 "looks_binary": "[1, 2]",
+                 "empty_binary": [],
+                 "nested": {
+                   "inner": {
+                     "deep": {
+                       "foo": "bar"
+                     }
+                   },
+                   "sibling": "inner_sibling"
+                 },
+                 "sibling": "outer_sibling"
+               }"#,
+        )
+        .unwrap()
+    }
+
+    fn snapshot(path: &std::path::Path) -> std::collections::BTreeMap<std::path::PathBuf, Vec<u8>> {
+        fn walk(
+            path: &std::path::Path,
+            files: &mut std::collections::BTreeMap<std::path::PathBuf, Vec<u8>>,
+        ) {
+            for item in std::fs::read_dir(path).unwrap() {
+                let item = item.unwrap();
+                if item.file_name() == super::MARKER {
+                    continue;
+                }
+                if item.file_type().unwrap().is_dir() {
+                    walk(&item.path(), files);
+                } else {
+                    files.insert(item.path(), std::fs::read(item.path()).unwrap());
+                }
+            }
+        }
+
+        let mut files = std::collections::BTreeMap::new();
+        walk(path, &mut files);
+        files
+    }
+
+    #[test]
+    fn round_trip() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut store = make_store();
+        store.metadata_mut("binary").unwrap().notes = Some(String::from("note"));
+        store
+            .update(
+                "looks_binary",
+                store::Entry::String(String::from("[1, 2]").into()),
+            )
+            .unwrap();
+
+        super::save(&store, directory.path(), &crypter()).unwrap();
+        assert!(directory.path().join("nested").join("inner").is_dir());
+        assert!(directory.path().join("binary").is_file());
+
+        let loaded = super::load(directory.path(), &crypter()).unwrap();
+        assert_eq!(loaded, store);
+        assert_eq!(
+            loaded.read("binary").unwrap(),
+            &store::Entry::Binary(vec![245, 107, 95, 100].into())
+        );
+        assert_eq!(
+            loaded.read("looks_binary").unwrap(),
+            &store::Entry::String(String::from("[1, 2]").into())
+        );
+        assert_eq!(
+            loaded.read("empty_binary").unwrap(),
+            &store::Entry::Binary(Vec::new().into())
+        );
+        assert_eq!(loaded.metadata("binary"), store.metadata("binary"));
+        assert_eq!(loaded.metadata("sibling"), store.metadata("sibling"));
+        assert_eq!(loaded.history("looks_binary").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn unchanged_files_are_not_rewritten() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut store = make_store();
+
+        super::save(&store, directory.path(), &crypter()).unwrap();
+        let before = snapshot(directory.path());
+
+        store
+            .update(
+                "sibling",
+                store::Entry::String(String::from("changed").into()),
+            )
+            .unwrap();
+        super::save(&store, directory.path(), &crypter()).unwrap();
+        let after = snapshot(directory.path());
+
+        let changed = before
+            .iter()
+            .filter(|(path, data)| after.get(*path) != Some(data))
+            .map(|(path, _)| path.strip_prefix(directory.path()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(changed, [std::path::Path::new("sibling")]);
+    }
+
+    #[test]
+    fn rewrite() {
+        let directory = tempfile::tempdir().unwrap();
+        let store = make_store();
+        let rekeyed = store::Crypter::new("new").with_kdf(store::Kdf::Pbkdf2 { rounds: 16 });
+
+        super::save(&store, directory.path(), &crypter()).unwrap();
+        let before = snapshot(directory.path());
+
+        super::rewrite(&store, directory.path(), &rekeyed).unwrap();
+        let after = snapshot(directory.path());
+
+        assert_eq!(
+            before.keys().collect::<Vec<_>>(),
+            after.keys().collect::<Vec<_>>()
+        );
+        assert!(before.iter().all(|(path, data)| after[path] != *data));
+        assert!(super::load(directory.path(), &crypter()).is_err());
+        assert_eq!(super::load(directory.path(), &rekeyed).unwrap(), store);
+    }
+
+    #[test]
+    fn removed_entries_are_deleted() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut store = make_store();
+
+        super::save(&store, directory.path(), &crypter()).unwrap();
+        std::fs::create_dir(directory.path().join(".git")).unwrap();
+
+        store.delete("nested").unwrap();
+        store.delete("binary").unwrap();
+        store
+            .update("sibling", store::Entry::Nested(make_store()))
+            .unwrap();
+        super::save(&store, directory.path(), &crypter()).unwrap();
+
+        assert!(!directory.path().join("nested").exists());
+        assert!(!directory.path().join("binary").exists());
+        assert!(directory.path().join("sibling").is_dir());
+        assert!(directory.path().join(".git").is_dir());
+        assert_eq!(super::load(directory.path(), &crypter()).unwrap(), store);
+    }
+
+    #[test]
+    fn unrelated_files_are_kept() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut store = make_store();
+
+        super::save(&store, directory.path(), &crypter()).unwrap();
+        assert!(directory.path().join(super::MARKER).is_file());
+        let deep = directory.path().join("nested").join("inner");
+        std::fs::write(deep.join("notes.txt"), b"unrelated").unwrap();
+        std::fs::write(directory.path().join("binary.bak"), b"unrelated").unwrap();
+
+        store.delete("nested").unwrap();
+        super::save(&store, directory.path(), &crypter()).unwrap();
+        assert_eq!(std::fs::read(deep.join("notes.txt")).unwrap(), b"unrelated");
+        assert!(!deep.join("deep").exists());
+        assert!(!directory.path().join("nested").join("sibling").exists());
+        assert!(directory.path().join("binary.bak").is_file());
+
+        // Which are skipped when loading
+        assert_eq!(super::load(directory.path(), &crypter()).unwrap(), store);
+
+        // Neither are secrets of another store
+        std::fs::remove_file(deep.join("notes.txt")).unwrap();
+        let other = store::Crypter::new("other").with_kdf(store::Kdf::Pbkdf2 { rounds: 16 });
+        super::save(&make_store(), directory.path(), &other).unwrap();
+        store.delete("binary").unwrap();
+        super::save(&store, directory.path(), &crypter()).unwrap();
+        assert!(directory.path().join("binary").is_file());
+    }
+
+    #[test]
+    fn settings() {
+        let directory = tempfile::tempdir().unwrap();
+        let marker = directory.path().join(super::MARKER);
+        let mut store = make_store();
+
+        // The defaults leave the marker empty
+        super::save(&store, directory.path(), &crypter()).unwrap();
+        assert!(std::fs::read(&marker).unwrap().is_empty());
+
+        store.settings_mut().retention = 3;
+        super::save(&store, directory.path(), &crypter()).unwrap();
+        let written = std::fs::read(&marker).unwrap();
+        assert!(!written.is_empty());
+        assert_eq!(
+            super::load(directory.path(), &crypter())
+                .unwrap()
+                .settings()
+                .retention,
+            3
+        );
+
+        // Unchanged settings are not written again
+        super::save(&store, directory.path(), &crypter()).unwrap();
+        assert_eq!(std::fs::read(&marker).unwrap(), written);
+
+        let rekeyed = store::Crypter::new("new").with_kdf(store::Kdf::Pbkdf2 { rounds: 16 });
+        super::rewrite(&store, directory.path(), &rekeyed).unwrap();
+        assert_eq!(
+            super::load(directory.path(), &rekeyed)
+                .unwrap()
+                .settings()
+                .retention,
+            3
+        );
+    }
+
+    #[test]
+    fn stray_files_are_skipped() {
+        let directory = tempfile::tempdir().unwrap();
+        let store = make_store();
+
+        super::save(&store, directory.path(), &crypter()).unwrap();
+        std::fs::write(directory.path().join("README.md"), b"unrelated").unwrap();
+        std::fs::write(directory.path().join("%zz"), b"unrelated").unwrap();
+        super::save(&store, directory.path(), &crypter()).unwrap();
+
+        assert_eq!(super::load(directory.path(), &crypter()).unwrap(), store);
+        // Unless nothing is left, as with the wrong password
+        let wrong = store::Crypter::new("wrong").with_kdf(store::Kdf::Pbkdf2 { rounds: 16 });
+        assert!(super::load(directory.path(), &wrong).is_err());
+    }
+
+    #[test]
+    fn only_stores_are_written() {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::write(directory.path().join("unrelated"), b"unrelated").unwrap();
+        assert!(!super::is_store(directory.path()));
+        assert!(super::save(&make_store(), directory.path(), &crypter()).is_err());
+        assert!(super::rewrite(&make_store(), directory.path(), &crypter()).is_err());
+        assert_eq!(snapshot(directory.path()).len(), 1);
+
+        let new = directory.path().join("new");
+        assert!(!super::is_store(&new));
+        super::save(&make_store(), &new, &crypter()).unwrap();
+        assert!(super::is_store(&new));
+    }
+
+    #[test]
+    fn illegal_names() {
+        let names = [
+            "",
+            ".",
+            "..",
+            ".hidden",
+            "a/b",
+            "a\\b",
+            "CON",
+            "con.txt",
+            "trailing.",
+            "trailing ",
+            "100%",
+            "%41",
+            "a:b*c?",
+            "\u{e9}t\u{e9}",
+            "nul\0",
+        ];
+
+        let directory = tempfile::tempdir().unwrap();
+        let mut store = store::Store::new();
+        for name in &names {
+            store
+                .create(
+                    String::from(*name),
+                    store::Entry::String(String::from(*name).into()),
+                )
+                .unwrap();
+        }
+
+        super::save(&store, directory.path(), &crypter()).unwrap();
+        assert_eq!(snapshot(directory.path()).len(), names.len());
+        assert_eq!(super::load(directory.path(), &crypter()).unwrap(), store);
+    }
+
+    #[test]
+    fn case_collisions() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut store = make_store();
+        store
+            .create(
+                String::from("Sibling"),
+                store::Entry::String(String::from("upper").into()),
+            )
+            .unwrap();
+
+        assert!(super::save(&store, directory.path(), &crypter()).is_err());
+        assert!(super::rewrite(&store, directory.path(), &crypter()).is_err());
+        assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 0);
+
+        // Also within nested stores
+        store.delete("Sibling").unwrap();
+        let nested = serde_json::from_str(r#"{"inner": {"Token": "a", "token": "b"}}"#).unwrap();
+        store
+            .update("nested", store::Entry::Nested(nested))
+            .unwrap();
+        assert!(super::save(&store, directory.path(), &crypter()).is_err());
+    }
+
+    #[test]
+    fn encode() {
+        assert_eq!(super::encode("plain-name_1.txt"), "plain-name_1.txt");
+        assert_eq!(super::encode(""), "%");
+        assert_eq!(super::encode(".."), "%2E%2E");
+        assert_eq!(super::encode("a/b"), "a%2Fb");
+        assert_eq!(super::encode("CON"), "%43ON");
+        assert_eq!(super::encode("Con.txt"), "%43on.txt");
+        assert_eq!(super::encode("console"), "console");
+        assert_eq!(super::encode("end. "), "end.%20");
+        assert_eq!(super::encode("100%"), "100%25");
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!(super::decode("%").unwrap(), "");
+        assert_eq!(super::decode("a%2Fb").unwrap(), "a/b");
+        assert_eq!(super::decode("%C3%A9").unwrap(), "\u{e9}");
+        assert!(super::decode("a%2").is_none());
+        assert!(super::decode("a%zz").is_none());
+        assert!(super::decode("%FF").is_none());
+        // Not canonical
+        assert!(super::decode("%61").is_none());
+        assert!(super::decode("a%2fb").is_none());
+    }
+}
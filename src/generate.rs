@@ -0,0 +1,214 @@
+//! Generates random secrets, either from characters or as a passphrase of words
+//!
+//! Randomness comes from the operating system, and every choice is uniform over its alphabet
+
+/// The BIP-39 English wordlist, with 2048 words of 11 bits each
+const WORDS: &str = include_str!("wordlist.txt");
+
+/// Characters that are easily mistaken for one another
+const AMBIGUOUS: &str = "0O1Il|";
+
+/// Set of characters a generated secret may draw from
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Class {
+    Lower,
+    Upper,
+    Digits,
+    Symbols,
+}
+
+impl Class {
+    pub const ALL: [Self; 4] = [Self::Lower, Self::Upper, Self::Digits, Self::Symbols];
+
+    fn characters(self) -> &'static str {
+        match self {
+            Self::Lower => "abcdefghijklmnopqrstuvwxyz",
+            Self::Upper => "ABCDEFGHIJKLMNOPQRSTUVWXYZ",
+            Self::Digits => "0123456789",
+            // Quotes, backslashes, and backticks are left out, as they are awkward in shells
+            Self::Symbols => "!#$%&()*+,-./:;<=>?@[]^_{|}~",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Generator {
+    /// Characters drawn from `classes`, with at least one of each
+    Characters {
+        length: usize,
+        classes: Vec<Class>,
+        exclude_ambiguous: bool,
+    },
+    /// Words from the bundled wordlist, joined by `separator`
+    Words { count: usize, separator: String },
+}
+
+impl Generator {
+    pub fn generate(&self) -> anyhow::Result<zeroize::Zeroizing<String>> {
+        use rand::seq::SliceRandom;
+
+        let mut rng = rand::rngs::OsRng;
+
+        match self {
+            Self::Characters {
+                length,
+                classes,
+                exclude_ambiguous,
+            } => {
+                // Repeated classes would make their characters more likely
+                let mut unique = Vec::new();
+                for class in classes {
+                    if !unique.contains(class) {
+                        unique.push(*class);
+                    }
+                }
+                let alphabets = unique
+                    .iter()
+                    .map(|class| {
+                        class
+                            .characters()
+                            .chars()
+                            .filter(|c| !(*exclude_ambiguous && AMBIGUOUS.contains(*c)))
+                            .collect::<Vec<_>>()
+                    })
+                    .collect::<Vec<_>>();
+
+                if alphabets.is_empty() {
+                    anyhow::bail!("No character classes to generate from");
+                }
+                if *length < alphabets.len() {
+                    anyhow::bail!(
+                        "Length must be at least {} to include every class",
+                        alphabets.len()
+                    );
+                }
+
+                let mut alphabet = alphabets.concat();
+                alphabet.sort_unstable();
+                alphabet.dedup();
+                // Drawing again until every class is present keeps each character uniform. Every
+                // candidate is wiped, and allocated once so that growing it leaves no copies behind
+                loop {
+                    let mut secret = zeroize::Zeroizing::new(String::with_capacity(*length));
+                    for _ in 0..*length {
+                        secret.extend(alphabet.choose(&mut rng));
+                    }
+
+                    if alphabets
+                        .iter()
+                        .all(|alphabet| secret.chars().any(|c| alphabet.contains(&c)))
+                    {
+                        return Ok(secret);
+                    }
+                }
+            }
+            Self::Words { count, separator } => {
+                if *count == 0 {
+                    anyhow::bail!("Passphrases need at least one word");
+                }
+
+                let words = WORDS.lines().collect::<Vec<_>>();
+                Ok(zeroize::Zeroizing::new(
+                    (0..*count)
+                        .filter_map(|_| words.choose(&mut rng).copied())
+                        .collect::<Vec<_>>()
+                        .join(separator),
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Class, Generator};
+
+    #[test]
+    fn wordlist() {
+        let words = super::WORDS.lines().collect::<Vec<_>>();
+        assert_eq!(words.len(), 2048);
+        assert!(words.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn characters() {
+        let generator = Generator::Characters {
+            length: 4,
+            classes: Class::ALL.to_vec(),
+            exclude_ambiguous: true,
+        };
+
+        for _ in 0..100 {
+            let secret = generator.generate().unwrap();
+            assert_eq!(secret.chars().count(), 4);
+            for class in &Class::ALL {
+                assert!(secret.chars().any(|c| class.characters().contains(c)));
+            }
+            assert!(!secret.chars().any(|c| super::AMBIGUOUS.contains(c)));
+        }
+
+        let digits = Generator::Characters {
+            length: 32,
+            classes: vec![Class::Digits],
+            exclude_ambiguous: false,
+        }
+        .generate()
+        .unwrap();
+        assert!(digits.chars().all(|c| c.is_ascii_digit()));
+    }
+
+    #[test]
+    fn repeated_classes() {
+        // Counted once, so a single character is enough to include every class
+        let secret = Generator::Characters {
+            length: 1,
+            classes: vec![Class::Digits, Class::Digits],
+            exclude_ambiguous: false,
+        }
+        .generate()
+        .unwrap();
+        assert!(secret.chars().all(|c| c.is_ascii_digit()));
+    }
+
+    #[test]
+    fn words() {
+        let passphrase = Generator::Words {
+            count: 5,
+            separator: String::from(" "),
+        }
+        .generate()
+        .unwrap();
+
+        let words = passphrase.split(' ').collect::<Vec<_>>();
+        assert_eq!(words.len(), 5);
+        assert!(words
+            .iter()
+            .all(|word| super::WORDS.lines().any(|w| w == *word)));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(Generator::Characters {
+            length: 3,
+            classes: Class::ALL.to_vec(),
+            exclude_ambiguous: false,
+        }
+        .generate()
+        .is_err());
+
+        assert!(Generator::Characters {
+            length: 3,
+            classes: Vec::new(),
+            exclude_ambiguous: false,
+        }
+        .generate()
+        .is_err());
+
+        assert!(Generator::Words {
+            count: 0,
+            separator: String::from("-"),
+        }
+        .generate()
+        .is_err());
+    }
+}
@@ -0,0 +1,132 @@
+//! Reads the value of a secret from wherever the command line asked, so that it need not go
+//! through argv
+
+/// The value for `entry`, read from its source
+pub fn value(entry: &super::args::Entry) -> anyhow::Result<store::Entry> {
+    let input = &entry.input;
+    if input.kind.is_some() && !input.stdin && input.file.is_none() {
+        anyhow::bail!("`--as` only applies to values read with `--stdin` or `--file`");
+    }
+
+    if entry.generate {
+        let mut generated = entry.options.generator().generate()?;
+        // Shown on stderr, since stdout may carry the store
+        if entry.options.show {
+            eprintln!("{}", generated.as_str());
+        }
+        Ok(store::Entry::String(std::mem::take(&mut *generated).into()))
+    } else if input.stdin {
+        from_bytes(strip_newline(super::read_from_stdin()?), input.kind)
+    } else if let Some(path) = &input.file {
+        from_bytes(std::fs::read(path)?, input.kind)
+    } else if input.prompt {
+        prompt()
+    } else {
+        entry
+            .secret
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("Missing value for the secret"))
+    }
+}
+
+/// Strings unless forced otherwise or not valid UTF-8
+fn from_bytes(bytes: Vec<u8>, kind: Option<super::args::Kind>) -> anyhow::Result<store::Entry> {
+    match kind {
+        Some(super::args::Kind::Binary) => Ok(store::Entry::Binary(bytes.into())),
+        Some(super::args::Kind::String) => {
+            Ok(store::Entry::String(String::from_utf8(bytes)?.into()))
+        }
+        None => Ok(String::from_utf8(bytes).map_or_else(
+            |error| store::Entry::Binary(error.into_bytes().into()),
+            |string| store::Entry::String(string.into()),
+        )),
+    }
+}
+
+// A single line piped in, as from `echo`, should not keep its line break
+fn strip_newline(mut bytes: Vec<u8>) -> Vec<u8> {
+    if bytes.ends_with(b"\n") {
+        bytes.pop();
+        if bytes.ends_with(b"\r") {
+            bytes.pop();
+        }
+    }
+    bytes
+}
+
+fn prompt() -> anyhow::Result<store::Entry> {
+    let secret = store::Secret::new(rpassword::read_password_from_tty(Some("Secret: "))?);
+    if secret.expose().is_empty() {
+        anyhow::bail!("Empty secret");
+    }
+    let confirmation =
+        zeroize::Zeroizing::new(rpassword::read_password_from_tty(Some("Confirm secret: "))?);
+    if *confirmation != *secret.expose() {
+        anyhow::bail!("Secrets do not match");
+    }
+    Ok(store::Entry::String(secret))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::args::Kind;
+
+    #[test]
+    fn from_bytes() {
+        assert_eq!(
+            super::from_bytes(b"text".to_vec(), None).unwrap(),
+            store::Entry::String(String::from("text").into())
+        );
+        assert_eq!(
+            super::from_bytes(vec![0xff, 0x00], None).unwrap(),
+            store::Entry::Binary(vec![0xff, 0x00].into())
+        );
+        assert_eq!(
+            super::from_bytes(b"text".to_vec(), Some(Kind::Binary)).unwrap(),
+            store::Entry::Binary(b"text".to_vec().into())
+        );
+        assert!(super::from_bytes(vec![0xff], Some(Kind::String)).is_err());
+    }
+
+    #[test]
+    fn strip_newline() {
+        assert_eq!(super::strip_newline(b"secret\n".to_vec()), b"secret");
+        assert_eq!(super::strip_newline(b"secret\r\n".to_vec()), b"secret");
+        assert_eq!(super::strip_newline(b"secret\n\n".to_vec()), b"secret\n");
+        assert_eq!(super::strip_newline(b"secret\r".to_vec()), b"secret\r");
+    }
+
+    #[test]
+    fn file() {
+        use clap::Clap;
+
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("key");
+        std::fs::write(&path, b"line\n").unwrap();
+
+        let value = |argv: &[&str]| match crate::args::Args::try_parse_from(argv).unwrap().action {
+            crate::args::Action::Create(entry) => super::value(&entry),
+            action => panic!("Unexpected action: {:?}", action),
+        };
+        let path = path.to_str().unwrap();
+
+        assert_eq!(
+            value(&["passifier", "create", "key", "--file", path]).unwrap(),
+            store::Entry::String(String::from("line\n").into())
+        );
+        assert_eq!(
+            value(&[
+                "passifier",
+                "create",
+                "key",
+                "--file",
+                path,
+                "--as",
+                "binary"
+            ])
+            .unwrap(),
+            store::Entry::Binary(b"line\n".to_vec().into())
+        );
+        assert!(value(&["passifier", "create", "key", "value", "--as", "binary"]).is_err());
+    }
+}
@@ -0,0 +1,356 @@
+//! Shows the structure of a store, with names and kinds but no values unless asked for
+
+/// Kind of an entry, as shown in listings
+pub fn kind(entry: &store::Entry) -> &'static str {
+    match entry {
+        store::Entry::String(_) => "string",
+        store::Entry::Binary(_) => "binary",
+        store::Entry::Nested(_) => "nested",
+    }
+}
+
+/// Lines of `PATH<TAB>KIND` for the entries under `path`, descending `depth` levels
+///
+/// Paths are written in full, so that they can be pasted back into other commands
+pub fn list(
+    store: &store::Store,
+    path: &store::Path,
+    depth: usize,
+) -> Result<Vec<String>, store::StoreError> {
+    fn walk(store: &store::Store, path: &store::Path, depth: usize, lines: &mut Vec<String>) {
+        for (name, entry) in store.iter() {
+            let mut child = path.clone();
+            child.push(name.as_str());
+            lines.push(format!("{child}\t{}", kind(entry)));
+
+            if let store::Entry::Nested(nested) = entry {
+                if depth > 1 {
+                    walk(nested, &child, depth - 1, lines);
+                }
+            }
+        }
+    }
+
+    let mut lines = Vec::new();
+    match Node::lookup(store, path)? {
+        Node::Store(nested) => {
+            if depth > 0 {
+                walk(nested, path, depth, &mut lines);
+            }
+        }
+        Node::Entry(entry) => lines.push(format!("{path}\t{}", kind(entry))),
+    }
+    Ok(lines)
+}
+
+/// Lines drawing the entries under `path` as a tree, descending at most `depth` levels
+pub fn tree(
+    store: &store::Store,
+    path: &store::Path,
+    depth: Option<usize>,
+) -> Result<Vec<String>, store::StoreError> {
+    fn walk(store: &store::Store, prefix: &str, depth: Option<usize>, lines: &mut Vec<String>) {
+        let count = store.iter().count();
+
+        for (index, (name, entry)) in store.iter().enumerate() {
+            let (branch, indent) = if index + 1 == count {
+                ("\u{2514}\u{2500}\u{2500} ", "    ")
+            } else {
+                ("\u{251c}\u{2500}\u{2500} ", "\u{2502}   ")
+            };
+            lines.push(format!(
+                "{prefix}{branch}{} ({})",
+                display_name(name),
+                kind(entry)
+            ));
+
+            if let store::Entry::Nested(nested) = entry {
+                if depth.is_none_or(|depth| depth > 1) {
+                    walk(
+                        nested,
+                        &format!("{prefix}{indent}"),
+                        depth.map(|depth| depth - 1),
+                        lines,
+                    );
+                }
+            }
+        }
+    }
+
+    let node = Node::lookup(store, path)?;
+    let root = if path.is_empty() {
+        String::from(".")
+    } else {
+        path.to_string()
+    };
+
+    let mut lines = vec![format!("{root} ({})", node.kind())];
+    if let Node::Store(nested) = node {
+        if depth != Some(0) {
+            walk(nested, "", depth, &mut lines);
+        }
+    }
+    Ok(lines)
+}
+
+/// Lines describing the secret at `path` and its metadata, without its value
+pub fn info(store: &store::Store, path: &store::Path) -> Result<Vec<String>, store::StoreError> {
+    let entry = store.read_at(path)?;
+    let metadata = store.metadata_at(path)?;
+
+    let mut lines = vec![
+        format!("path:     {path}"),
+        format!("kind:     {}", kind(entry)),
+        format!("created:  {}", timestamp(metadata.created, "unknown")),
+        format!("modified: {}", timestamp(metadata.modified, "unknown")),
+        format!("accessed: {}", timestamp(metadata.accessed, "never")),
+    ];
+
+    if !metadata.tags.is_empty() {
+        let tags = metadata.tags.iter().map(String::as_str).collect::<Vec<_>>();
+        lines.push(format!("tags:     {}", tags.join(", ")));
+    }
+    if let Some(url) = &metadata.url {
+        lines.push(format!("url:      {url}"));
+    }
+    if let Some(notes) = &metadata.notes {
+        lines.push(format!("notes:    {notes}"));
+    }
+
+    Ok(lines)
+}
+
+/// Lines of `VERSION<TAB>MODIFIED<TAB>KIND` for the previous values of the secret at `path`
+///
+/// Versions are numbered from the most recent, as expected by
+/// [`rollback_at`](../store/struct.Store.html#method.rollback_at). Values are appended in JSON
+/// only when `values` is set
+pub fn history(
+    store: &store::Store,
+    path: &store::Path,
+    values: bool,
+) -> anyhow::Result<Vec<String>> {
+    store
+        .history_at(path)?
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(index, version)| {
+            let mut line = format!(
+                "{}\t{}\t{}",
+                index + 1,
+                timestamp(version.modified, "unknown"),
+                kind(&version.entry)
+            );
+            if values {
+                line.push('\t');
+                line.push_str(&serde_json::to_string(&version.entry)?);
+            }
+            Ok(line)
+        })
+        .collect()
+}
+
+fn timestamp(seconds: Option<u64>, missing: &str) -> String {
+    seconds.map_or_else(
+        || String::from(missing),
+        |seconds| {
+            let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(seconds);
+            humantime::format_rfc3339_seconds(time).to_string()
+        },
+    )
+}
+
+/// What `path` points at, with the empty path being the whole store
+enum Node<'a> {
+    Store(&'a store::Store),
+    Entry(&'a store::Entry),
+}
+
+impl<'a> Node<'a> {
+    fn lookup(store: &'a store::Store, path: &store::Path) -> Result<Self, store::StoreError> {
+        if path.is_empty() {
+            return Ok(Self::Store(store));
+        }
+
+        Ok(match store.read_at(path)? {
+            store::Entry::Nested(nested) => Self::Store(nested),
+            entry => Self::Entry(entry),
+        })
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Store(_) => "nested",
+            Self::Entry(entry) => kind(entry),
+        }
+    }
+}
+
+/// A single name, quoted as it would be in a path
+fn display_name(name: &str) -> String {
+    std::iter::once(name).collect::<store::Path>().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    fn make_store() -> store::Store {
+        serde_json::from_str(
+            r#"{
+                 "sibling": "outer_sibling",
+                 "binary": [ 245, 107, 95, 100 ],
+                 "nested": {
+                   "inner": {
+                     "deep": {
+                       "foo": "bar"
+                     }
+                   },
+                   "example.com": "inner_sibling"
+                 }
+               }"#,
+        )
+        .unwrap()
+    }
+
+    fn path(string: &str) -> store::Path {
+        string.parse().unwrap()
+    }
+
+    #[test]
+    fn list() {
+        let store = make_store();
+
+        assert_eq!(
+            super::list(&store, &store::Path::new(), 1).unwrap(),
+            ["binary\tbinary", "nested\tnested", "sibling\tstring"]
+        );
+        assert_eq!(
+            super::list(&store, &path("nested"), 2).unwrap(),
+            [
+                "nested.\"example.com\"\tstring",
+                "nested.inner\tnested",
+                "nested.inner.deep\tnested",
+            ]
+        );
+        assert_eq!(
+            super::list(&store, &path("nested.inner.deep.foo"), 1).unwrap(),
+            ["nested.inner.deep.foo\tstring"]
+        );
+        assert!(super::list(&store, &path("missing"), 1).is_err());
+    }
+
+    #[test]
+    fn list_never_shows_values() {
+        let store = make_store();
+        let listing = super::list(&store, &store::Path::new(), usize::MAX)
+            .unwrap()
+            .join("\n");
+
+        assert!(!listing.contains("outer_sibling"));
+        assert!(!listing.contains("inner_sibling"));
+        assert!(!listing.contains("bar"));
+        assert!(!listing.contains("245"));
+    }
+
+    #[test]
+    fn tree() {
+        let store = make_store();
+
+        assert_eq!(
+            super::tree(&store, &store::Path::new(), None)
+                .unwrap()
+                .join("\n"),
+            "\
+. (nested)
+\u{251c}\u{2500}\u{2500} binary (binary)
+\u{251c}\u{2500}\u{2500} nested (nested)
+\u{2502}   \u{251c}\u{2500}\u{2500} \"example.com\" (string)
+\u{2502}   \u{2514}\u{2500}\u{2500} inner (nested)
+\u{2502}       \u{2514}\u{2500}\u{2500} deep (nested)
+\u{2502}           \u{2514}\u{2500}\u{2500} foo (string)
+\u{2514}\u{2500}\u{2500} sibling (string)"
+        );
+
+        assert_eq!(
+            super::tree(&store, &path("nested"), Some(1))
+                .unwrap()
+                .join("\n"),
+            "\
+nested (nested)
+\u{251c}\u{2500}\u{2500} \"example.com\" (string)
+\u{2514}\u{2500}\u{2500} inner (nested)"
+        );
+
+        assert_eq!(
+            super::tree(&store, &path("binary"), None).unwrap(),
+            ["binary (binary)"]
+        );
+    }
+
+    #[test]
+    fn info() {
+        let mut store = make_store();
+
+        assert_eq!(
+            super::info(&store, &path("sibling")).unwrap(),
+            [
+                "path:     sibling",
+                "kind:     string",
+                "created:  unknown",
+                "modified: unknown",
+                "accessed: never",
+            ]
+        );
+
+        let metadata = store.metadata_at_mut(&path("nested.inner")).unwrap();
+        metadata.created = Some(0);
+        metadata.modified = Some(86_400);
+        metadata.tags.insert(String::from("work"));
+        metadata.tags.insert(String::from("mail"));
+        metadata.url = Some(String::from("https://example.com"));
+        metadata.notes = Some(String::from("Shared"));
+
+        assert_eq!(
+            super::info(&store, &path("nested.inner")).unwrap(),
+            [
+                "path:     nested.inner",
+                "kind:     nested",
+                "created:  1970-01-01T00:00:00Z",
+                "modified: 1970-01-02T00:00:00Z",
+                "accessed: never",
+                "tags:     mail, work",
+                "url:      https://example.com",
+                "notes:    Shared",
+            ]
+        );
+        assert!(super::info(&store, &path("missing")).is_err());
+    }
+
+    #[test]
+    fn history() {
+        let mut store = make_store();
+        let sibling = path("sibling");
+        store
+            .update_at(&sibling, store::Entry::Binary(vec![1].into()))
+            .unwrap();
+        store
+            .update_at(
+                &sibling,
+                store::Entry::String(String::from("latest").into()),
+            )
+            .unwrap();
+
+        let lines = super::history(&store, &sibling, false).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("1\t") && lines[0].ends_with("\tbinary"));
+        assert_eq!(lines[1], "2\tunknown\tstring");
+
+        let lines = super::history(&store, &sibling, true).unwrap();
+        assert!(lines[0].ends_with("\tbinary\t[1]"));
+        assert_eq!(lines[1], "2\tunknown\tstring\t\"outer_sibling\"");
+
+        assert!(super::history(&store, &path("nested"), false)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -1,12 +1,49 @@
 #![deny(warnings, rust_2018_idioms, clippy::pedantic)]
 
 mod args;
-mod ops;
+mod directory;
+mod generate;
+mod input;
+mod listing;
+mod output;
+mod s3;
+mod shell;
 
+// Writes next to the destination and renames over it, so a failed write never leaves a partial
+// store behind
 fn save_to_file<P: AsRef<std::path::Path>>(data: &[u8], path: P) -> Result<(), std::io::Error> {
+    let path = path.as_ref();
+    write_next_to(data, path)?
+        .persist(path)
+        .map_err(|error| error.error)?;
+    Ok(())
+}
+
+// Same as `save_to_file`, but fails if something is already at `path`, even if it only appeared
+// while writing
+fn create_file<P: AsRef<std::path::Path>>(data: &[u8], path: P) -> Result<(), std::io::Error> {
+    let path = path.as_ref();
+    write_next_to(data, path)?
+        .persist_noclobber(path)
+        .map_err(|error| error.error)?;
+    Ok(())
+}
+
+fn write_next_to(
+    data: &[u8],
+    path: &std::path::Path,
+) -> Result<tempfile::NamedTempFile, std::io::Error> {
     use std::io::Write;
 
-    std::fs::File::create(path)?.write_all(data).map(|_| ())
+    let directory = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or_else(|| std::path::Path::new("."));
+
+    let mut file = tempfile::NamedTempFile::new_in(directory)?;
+    file.write_all(data)?;
+    file.as_file().sync_all()?;
+    Ok(file)
 }
 
 fn read_from_file<P: AsRef<std::path::Path>>(path: P) -> Result<Vec<u8>, std::io::Error> {
@@ -18,55 +55,500 @@ fn read_from_file<P: AsRef<std::path::Path>>(path: P) -> Result<Vec<u8>, std::io
     Ok(buffer)
 }
 
+fn read_from_stdin() -> Result<Vec<u8>, std::io::Error> {
+    use std::io::Read;
+
+    let mut buffer = Vec::new();
+    std::io::stdin().read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
+
+fn write_to_stdout(data: &[u8]) -> anyhow::Result<()> {
+    use std::io::IsTerminal;
+    use std::io::Write;
+
+    let mut stdout = std::io::stdout();
+    if stdout.is_terminal() {
+        anyhow::bail!("Refusing to write binary data to a terminal");
+    }
+    stdout.write_all(data)?;
+    Ok(stdout.flush()?)
+}
+
+// Prompts on the terminal, since stdin may be carrying the store itself. Passwords are wiped
+// from memory once dropped
+fn prompt_password() -> Result<zeroize::Zeroizing<String>, std::io::Error> {
+    rpassword::read_password_from_tty(Some("Password: ")).map(zeroize::Zeroizing::new)
+}
+
+// Asks twice, since a mistyped password would lock the store for good
+fn prompt_new_password() -> anyhow::Result<zeroize::Zeroizing<String>> {
+    let password =
+        zeroize::Zeroizing::new(rpassword::read_password_from_tty(Some("New password: "))?);
+    if password.is_empty() {
+        anyhow::bail!("Empty password");
+    }
+    let confirmation = zeroize::Zeroizing::new(rpassword::read_password_from_tty(Some(
+        "Confirm new password: ",
+    ))?);
+    if confirmation != password {
+        anyhow::bail!("Passwords do not match");
+    }
+    Ok(password)
+}
+
+fn new_crypter(arguments: &args::Args) -> anyhow::Result<store::Crypter> {
+    // Recovery goes around the password or identity, and sets a new password right away
+    if let args::Action::Recover(recover) = &arguments.action {
+        let shares = read_shares()?;
+        return Ok(unlock(
+            prompt_new_password,
+            recover.key_file.as_deref(),
+            recover.with_password,
+            arguments.compression,
+            arguments.cipher,
+        )?
+        .with_shares(&shares)?
+        .with_recipients(Vec::new()));
+    }
+
+    if let Some(path) = &arguments.identity {
+        let identity = zeroize::Zeroizing::new(String::from_utf8(read_from_file(path)?)?)
+            .parse::<store::Identity>()?;
+        let crypter = store::Crypter::from_identity(identity)
+            .with_compression(arguments.compression.unwrap_or_default());
+        return Ok(match arguments.cipher {
+            Some(cipher) => crypter.with_cipher(cipher),
+            None => crypter,
+        });
+    }
+
+    unlock(
+        || Ok(prompt_password()?),
+        arguments.key_file.as_deref(),
+        arguments.with_password,
+        arguments.compression,
+        arguments.cipher,
+    )
+}
+
+// One share per line, as printed by `split`
+fn read_shares() -> anyhow::Result<Vec<store::Share>> {
+    use std::io::IsTerminal;
+
+    if std::io::stdin().is_terminal() {
+        eprintln!("Enter the shares, one per line, then end with Ctrl-D");
+    }
+    let shares = zeroize::Zeroizing::new(String::from_utf8(read_from_stdin()?)?)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(str::parse)
+        .collect::<Result<_, _>>()?;
+    Ok(shares)
+}
+
+// The password is only asked for when there is no key file, or when both are required. The
+// derived key is kept, so that saving back does not derive it again. Without a cipher, the one
+// of the store is kept
+fn unlock<F: FnOnce() -> anyhow::Result<zeroize::Zeroizing<String>>>(
+    password: F,
+    key_file: Option<&std::path::Path>,
+    with_password: bool,
+    compression: Option<store::Compression>,
+    cipher: Option<store::Cipher>,
+) -> anyhow::Result<store::Crypter> {
+    let crypter = match key_file {
+        Some(path) => {
+            let key = zeroize::Zeroizing::new(read_from_file(path)?);
+            if key.is_empty() {
+                anyhow::bail!("Empty key file: {}", path.display());
+            }
+
+            if with_password {
+                store::Crypter::new(password()?).with_key_file(&key)
+            } else {
+                store::Crypter::from_key(&key)
+            }
+        }
+        None => store::Crypter::new(password()?),
+    };
+
+    let crypter = crypter
+        .with_key_reuse()
+        .with_compression(compression.unwrap_or_default());
+    Ok(match cipher {
+        Some(cipher) => crypter.with_cipher(cipher),
+        None => crypter,
+    })
+}
+
+fn load(
+    source: &args::Source,
+    arguments: &args::Args,
+    etag: &mut Option<String>,
+) -> anyhow::Result<(store::Store, store::Crypter)> {
+    let data = match source {
+        args::Source::Directory(path) => {
+            let crypter = new_crypter(arguments)?;
+            return Ok((directory::load(path, &crypter)?, crypter));
+        }
+        args::Source::File(path) => read_from_file(path)?,
+        args::Source::Pipe => read_from_stdin()?,
+        args::Source::S3 { bucket, key } => {
+            let object = s3::Client::from_env(arguments.s3_endpoint.clone())?.get(bucket, key)?;
+            *etag = object.etag;
+            object.data
+        }
+    };
+
+    let crypter = new_crypter(arguments)?;
+    let store = store::Store::decrypt_with(&data, &crypter)?;
+    Ok((store, crypter))
+}
+
+fn save(
+    store: &store::Store,
+    destination: &args::Source,
+    crypter: &store::Crypter,
+    arguments: &args::Args,
+    etag: &mut Option<String>,
+) -> anyhow::Result<()> {
+    match destination {
+        args::Source::File(path) => save_to_file(&store.encrypt_with(crypter)?, path)?,
+        args::Source::Pipe => write_to_stdout(&store.encrypt_with(crypter)?)?,
+        args::Source::Directory(path) => directory::save(store, path, crypter)?,
+        args::Source::S3 { bucket, key } => {
+            let condition = match etag.take() {
+                Some(etag) => s3::Condition::IfMatch(etag),
+                None => s3::Condition::IfNoneMatch,
+            };
+            *etag = s3::Client::from_env(arguments.s3_endpoint.clone())?.put(
+                bucket,
+                key,
+                &store.encrypt_with(crypter)?,
+                &condition,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
 fn main() -> anyhow::Result<()> {
     use clap::Clap;
     let arguments = args::Args::parse();
 
-    let mut store = if let Some(source) = arguments.store {
-        match source {
-            args::Source::File(path) => {
-                let data = read_from_file(path)?;
-                let password = rpassword::prompt_password_stderr("Password: ")?;
-                store::Store::decrypt(&data, password)?
+    let destination = match &arguments.save {
+        Some(save_to) => Some(
+            save_to
+                .as_ref()
+                .or(arguments.store.as_ref())
+                .ok_or_else(|| anyhow::anyhow!("No INPUT to save back to, OUTPUT is required"))?,
+        ),
+        None if matches!(arguments.action, args::Action::Shell)
+            || arguments.action.handles_encryption() =>
+        {
+            arguments.store.as_ref()
+        }
+        None => None,
+    };
+
+    if arguments.store == Some(args::Source::Pipe) && arguments.action.reads_stdin() {
+        anyhow::bail!("Stdin cannot carry both the store and the secret");
+    }
+
+    // ETag of the destination S3 object, so that saves do not clobber concurrent ones. Without
+    // one, the object must not exist yet
+    let mut etag = None;
+
+    let (mut store, mut crypter) = match &arguments.store {
+        Some(source) => {
+            let (store, crypter) = load(source, &arguments, &mut etag)?;
+            if destination != Some(source) {
+                etag = None;
+            }
+            (store, Some(crypter))
+        }
+        None => (store::Store::new(), None),
+    };
+
+    if let args::Action::Shell = arguments.action {
+        return shell::run(&mut store, |store| {
+            let destination = destination
+                .ok_or_else(|| anyhow::anyhow!("Nothing to save to, OUTPUT is required"))?;
+            if crypter.is_none() {
+                crypter = Some(new_crypter(&arguments)?);
+            }
+            let crypter = crypter.as_ref().unwrap();
+            save(store, destination, crypter, &arguments, &mut etag)
+        });
+    }
+
+    if arguments.action.handles_encryption() {
+        if let (Some(crypter), Some(destination)) = (crypter, destination) {
+            return reencrypt(&store, crypter, destination, &arguments, &mut etag);
+        }
+        anyhow::bail!("Nothing to encrypt again, INPUT is required");
+    }
+
+    let modified = arguments.action.modifies();
+    execute(
+        &arguments.action,
+        &mut store,
+        modified && destination.is_none(),
+    )?;
+
+    if let Some(destination) = destination {
+        // Reads are only recorded when saving anyway, so that reading never writes on its own
+        if let args::Action::Read(read) = &arguments.action {
+            store.metadata_at_mut(&read.path)?.mark_read();
+        }
+
+        let crypter = match crypter {
+            Some(crypter) => crypter,
+            None => new_crypter(&arguments)?,
+        };
+        save(&store, destination, &crypter, &arguments, &mut etag)?;
+    } else if modified {
+        // Without a destination, pass the modified store along the pipe
+        pipe(&store, crypter, &arguments)?;
+    }
+
+    Ok(())
+}
+
+// Only the encryption changes, so the secrets are written again as they are. A fresh crypter
+// draws a new salt, and writes with the current format and key derivation. Shares are printed
+// only once the store they apply to is saved
+fn reencrypt(
+    store: &store::Store,
+    crypter: store::Crypter,
+    destination: &args::Source,
+    arguments: &args::Args,
+    etag: &mut Option<String>,
+) -> anyhow::Result<()> {
+    let mut shares = Vec::new();
+    let crypter = match &arguments.action {
+        args::Action::Rekey(rekey) => unlock(
+            prompt_new_password,
+            rekey.key_file.as_deref(),
+            rekey.with_password,
+            arguments.compression,
+            arguments.cipher.or_else(|| Some(crypter.cipher())),
+        )?,
+        args::Action::Recipients(recipients) => {
+            let mut current = crypter.recipients();
+            match &recipients.action {
+                args::RecipientsAction::List => {
+                    print_lines(current.iter().map(ToString::to_string).collect());
+                    return Ok(());
+                }
+                args::RecipientsAction::Add(add) => {
+                    if current.is_empty() {
+                        eprintln!("The store will no longer be encrypted with its password");
+                    }
+                    for recipient in &add.recipients {
+                        if !current.contains(recipient) {
+                            current.push(*recipient);
+                        }
+                    }
+                }
+                args::RecipientsAction::Remove(remove) => {
+                    if let Some(missing) = remove.recipients.iter().find(|r| !current.contains(r)) {
+                        anyhow::bail!("Not a recipient of the store: {}", missing);
+                    }
+                    current.retain(|recipient| !remove.recipients.contains(recipient));
+                    if current.is_empty() {
+                        anyhow::bail!("Cannot remove every recipient, use `rekey` instead");
+                    }
+                }
             }
-            args::Source::S3(_) => {
-                anyhow::bail!("S3 not yet implemented")
+            // Whoever was removed knows the data key, so it cannot be kept
+            if let args::RecipientsAction::Remove(_) = recipients.action {
+                crypter.with_recipients(current).with_new_data_key()
+            } else {
+                crypter.with_recipients(current)
+            }
+        }
+        args::Action::Split(split) => {
+            if *destination == args::Source::Pipe {
+                anyhow::bail!("Shares are printed on stdout, which cannot carry the store as well");
             }
+            shares = crypter.split(split.shares, split.threshold)?;
+            crypter
         }
-    } else {
-        store::Store::new()
+        args::Action::Recover(_) => crypter,
+        _ => unreachable!("Only rekeying, recipients, and shares change the encryption"),
     };
 
-    match arguments.action {
-        args::Action::Create(entry) => ops::create(&mut store, entry.path.as_ref(), entry.secret)?,
-        args::Action::Read(path) => {
-            let entry = ops::read(&store, path.path.as_ref())?;
-            println!("{}", serde_json::to_string(&entry)?);
+    match destination {
+        // Unchanged files would otherwise be kept with the old password or data key
+        args::Source::Directory(path) => directory::rewrite(store, path, &crypter)?,
+        destination => save(store, destination, &crypter, arguments, etag)?,
+    }
+
+    print_lines(shares.iter().map(ToString::to_string).collect());
+    Ok(())
+}
+
+// When `piping`, the modified store is the output, so nothing else is printed
+fn execute(action: &args::Action, store: &mut store::Store, piping: bool) -> anyhow::Result<()> {
+    match action {
+        args::Action::Create(entry) => {
+            store.create_at(&entry.path, input::value(entry)?)?;
+        }
+        args::Action::Read(read) => {
+            let data = output::format(store.read_at(&read.path)?, &read.path, read.format())?;
+            match &read.output {
+                Some(path) => save_to_file(&data, path)?,
+                None => output::print(&data)?,
+            }
+        }
+        args::Action::Update(entry) => {
+            store.update_at(&entry.path, input::value(entry)?)?;
+        }
+        args::Action::Delete(path) => {
+            store.delete_at(&path.path, store::Prune::EmptyParents)?;
+        }
+        args::Action::List(list) => {
+            let path = list.path.clone().unwrap_or_default();
+            print_lines(listing::list(store, &path, list.depth)?);
+        }
+        args::Action::Tree(tree) => {
+            let path = tree.path.clone().unwrap_or_default();
+            print_lines(listing::tree(store, &path, tree.depth)?);
+        }
+        args::Action::Info(info) => {
+            info.apply(store.metadata_at_mut(&info.path)?);
+            if !piping {
+                print_lines(listing::info(store, &info.path)?);
+            }
         }
-        args::Action::Update(entry) => ops::update(&mut store, entry.path.as_ref(), entry.secret)?,
-        args::Action::Delete(path) => ops::delete(&mut store, path.path.as_ref())?,
+        args::Action::History(history) => {
+            print_lines(listing::history(store, &history.path, history.values)?);
+        }
+        args::Action::Rollback(rollback) => {
+            store.rollback_at(&rollback.path, rollback.to)?;
+        }
+        args::Action::PruneHistory(prune) => {
+            let keep = prune.keep.unwrap_or(store.settings().retention);
+            store.prune_history_at(&prune.path.clone().unwrap_or_default(), keep)?;
+        }
+        args::Action::Retention(retention) => match retention.count {
+            Some(count) => store.settings_mut().retention = count,
+            None => println!("{}", store.settings().retention),
+        },
+        // Serializing is what reveals the values, so the output is wiped once printed
         args::Action::Print(print) => {
-            let json = if print.pretty {
+            let json = zeroize::Zeroizing::new(if print.pretty {
                 serde_json::to_string_pretty(&store)?
             } else {
                 serde_json::to_string(&store)?
-            };
+            });
 
-            println!("{}", json);
+            println!("{}", *json);
+        }
+        args::Action::Keygen(keygen) => {
+            let identity = store::Identity::generate();
+            let recipient = identity.recipient();
+            create_file(
+                zeroize::Zeroizing::new(format!("# public key: {recipient}\n{identity}\n"))
+                    .as_bytes(),
+                &keygen.output,
+            )
+            .map_err(|error| match error.kind() {
+                std::io::ErrorKind::AlreadyExists => {
+                    anyhow::anyhow!("Refusing to overwrite {}", keygen.output.display())
+                }
+                _ => error.into(),
+            })?;
+            println!("{recipient}");
+        }
+        args::Action::Shell
+        | args::Action::Rekey(_)
+        | args::Action::Recipients(_)
+        | args::Action::Split(_)
+        | args::Action::Recover(_) => {
+            unreachable!("The shell and changes to the encryption run on their own")
         }
     }
 
-    if let Some(save) = arguments.save {
-        match save {
-            args::Source::File(path) => {
-                let password = rpassword::prompt_password_stderr("Password: ")?;
-                save_to_file(&store.encrypt(password)?, path)?;
-            }
-            args::Source::S3(_) => {
-                anyhow::bail!("S3 not yet implemented")
-            }
+    Ok(())
+}
+
+fn print_lines(lines: Vec<String>) {
+    for line in lines {
+        println!("{line}");
+    }
+}
+
+fn pipe(
+    store: &store::Store,
+    crypter: Option<store::Crypter>,
+    arguments: &args::Args,
+) -> anyhow::Result<()> {
+    match arguments.pipe {
+        args::Format::Binary => {
+            let crypter = match crypter {
+                Some(crypter) => crypter,
+                None => new_crypter(arguments)?,
+            };
+            write_to_stdout(&store.encrypt_with(&crypter)?)?;
+        }
+        args::Format::Json | args::Format::Pretty => {
+            let json = zeroize::Zeroizing::new(if arguments.pipe == args::Format::Pretty {
+                serde_json::to_string_pretty(store)?
+            } else {
+                serde_json::to_string(store)?
+            });
+
+            println!("{}", *json);
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn save_to_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("store");
+
+        super::save_to_file(b"first", &path).unwrap();
+        super::save_to_file(b"second", &path).unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+        assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn create_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("identity");
+
+        super::create_file(b"first", &path).unwrap();
+        let error = super::create_file(b"second", &path).unwrap_err();
+
+        assert_eq!(error.kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read(&path).unwrap(), b"first");
+        assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn save_to_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("key");
+        std::fs::write(&path, b"public").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        super::save_to_file(b"private", &path).unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
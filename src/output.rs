@@ -0,0 +1,189 @@
+//! Formats the value of a secret for `read`, so that scripts can use it as is
+
+use super::args::ValueFormat;
+
+/// The value of `entry`, found at `path`, in `format`
+///
+/// Every format but raw ends with a line break. The value is wiped from memory once dropped
+pub fn format(
+    entry: &store::Entry,
+    path: &store::Path,
+    format: ValueFormat,
+) -> anyhow::Result<zeroize::Zeroizing<Vec<u8>>> {
+    use base64::Engine;
+
+    let mut data = zeroize::Zeroizing::new(match format {
+        ValueFormat::Raw => return Ok(bytes(entry)?.to_vec().into()),
+        ValueFormat::Json => serde_json::to_string(entry)?,
+        ValueFormat::Base64 => base64::engine::general_purpose::STANDARD.encode(bytes(entry)?),
+        ValueFormat::Hex => hex::encode(bytes(entry)?),
+        ValueFormat::Env => {
+            let mut lines = Vec::new();
+            match entry {
+                store::Entry::Nested(nested) => env(nested, &mut Vec::new(), &mut lines)?,
+                entry => {
+                    let name = path.segments().last().map(String::as_str);
+                    lines.push(env_line(name.into_iter(), entry)?);
+                }
+            }
+            let data = lines.join("\n");
+            lines.iter_mut().for_each(zeroize::Zeroize::zeroize);
+            data
+        }
+    });
+    data.push('\n');
+    Ok(std::mem::take(&mut *data).into_bytes().into())
+}
+
+/// Prints `data`, unless it is binary and stdout is a terminal
+pub fn print(data: &[u8]) -> anyhow::Result<()> {
+    use std::io::IsTerminal;
+    use std::io::Write;
+
+    let mut stdout = std::io::stdout();
+    if std::str::from_utf8(data).is_err() && stdout.is_terminal() {
+        anyhow::bail!("Refusing to write binary data to a terminal, use `--output` or `--format`");
+    }
+    stdout.write_all(data)?;
+    Ok(stdout.flush()?)
+}
+
+fn bytes(entry: &store::Entry) -> anyhow::Result<&[u8]> {
+    match entry {
+        store::Entry::String(string) => Ok(string.expose().as_bytes()),
+        store::Entry::Binary(binary) => Ok(binary.expose()),
+        store::Entry::Nested(_) => {
+            anyhow::bail!("Nested stores can only be read as json or env")
+        }
+    }
+}
+
+// Variables are named after the path relative to the secret being read
+fn env(
+    store: &store::Store,
+    parents: &mut Vec<String>,
+    lines: &mut Vec<String>,
+) -> anyhow::Result<()> {
+    for (name, entry) in store.iter() {
+        parents.push(name.clone());
+        if let store::Entry::Nested(nested) = entry {
+            env(nested, parents, lines)?;
+        } else {
+            lines.push(env_line(parents.iter().map(String::as_str), entry)?);
+        }
+        parents.pop();
+    }
+    Ok(())
+}
+
+// Quoted for the shell, so that the output can be sourced
+fn env_line<'a>(
+    segments: impl Iterator<Item = &'a str>,
+    entry: &store::Entry,
+) -> anyhow::Result<String> {
+    let mut name = segments
+        .map(|segment| {
+            segment
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() {
+                        c.to_ascii_uppercase()
+                    } else {
+                        '_'
+                    }
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("_");
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+
+    match entry {
+        store::Entry::String(value) => Ok(format!(
+            "{name}='{}'",
+            value.expose().replace('\'', r"'\''")
+        )),
+        _ => anyhow::bail!(
+            "Binary secret `{}` cannot be an environment variable, read it as base64 or hex",
+            name
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::args::ValueFormat;
+
+    macro_rules! entry {
+        ($string:literal) => {
+            store::Entry::String(String::from($string).into())
+        };
+    }
+
+    fn format(entry: &store::Entry, format: ValueFormat) -> anyhow::Result<String> {
+        let path = "db.password".parse::<store::Path>().unwrap();
+        Ok(String::from_utf8(
+            super::format(entry, &path, format)?.to_vec(),
+        )?)
+    }
+
+    #[test]
+    fn values() {
+        let string = entry!("pa\"ss");
+        assert_eq!(format(&string, ValueFormat::Raw).unwrap(), "pa\"ss");
+        assert_eq!(
+            format(&string, ValueFormat::Json).unwrap(),
+            "\"pa\\\"ss\"\n"
+        );
+        assert_eq!(format(&string, ValueFormat::Base64).unwrap(), "cGEic3M=\n");
+        assert_eq!(format(&string, ValueFormat::Hex).unwrap(), "7061227373\n");
+
+        let binary = store::Entry::Binary(vec![0xde, 0xad, 0xbe, 0xef].into());
+        let path = store::Path::new();
+        assert_eq!(
+            *super::format(&binary, &path, ValueFormat::Raw).unwrap(),
+            [0xde, 0xad, 0xbe, 0xef]
+        );
+        assert_eq!(
+            format(&binary, ValueFormat::Json).unwrap(),
+            "[222,173,190,239]\n"
+        );
+        assert_eq!(format(&binary, ValueFormat::Base64).unwrap(), "3q2+7w==\n");
+        assert_eq!(format(&binary, ValueFormat::Hex).unwrap(), "deadbeef\n");
+
+        let nested = store::Entry::Nested(store::Store::new());
+        assert!(format(&nested, ValueFormat::Raw).is_err());
+        assert!(format(&nested, ValueFormat::Base64).is_err());
+        assert!(format(&nested, ValueFormat::Hex).is_err());
+    }
+
+    #[test]
+    fn env() {
+        assert_eq!(
+            format(&entry!("it's"), ValueFormat::Env).unwrap(),
+            "PASSWORD='it'\\''s'\n"
+        );
+
+        let mut store = store::Store::new();
+        store
+            .create(String::from("host"), entry!("example.com"))
+            .unwrap();
+        store
+            .create_at(&"users.admin-1".parse().unwrap(), entry!("secret"))
+            .unwrap();
+        store
+            .create_at(&"1st".parse().unwrap(), entry!("first"))
+            .unwrap();
+        assert_eq!(
+            format(&store::Entry::Nested(store.clone()), ValueFormat::Env).unwrap(),
+            "_1ST='first'\nHOST='example.com'\nUSERS_ADMIN_1='secret'\n"
+        );
+
+        store
+            .create(String::from("key"), store::Entry::Binary(vec![1].into()))
+            .unwrap();
+        assert!(format(&store::Entry::Nested(store), ValueFormat::Env).is_err());
+    }
+}
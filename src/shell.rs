@@ -0,0 +1,533 @@
+//! Interactive shell over a decrypted store
+//!
+//! Secret values are never echoed back nor kept in the history, which only records the command
+//! and its paths
+
+const COMMANDS: &[&str] = &[
+    "create", "read", "update", "delete", "list", "move", "save", "quit", "help",
+];
+
+const HELP: &str = "\
+create PATH VALUE   Create new secret
+read PATH           Read an existing secret
+update PATH VALUE   Update an existing secret
+delete PATH         Delete an existing secret
+list [PATH]         List the names and kinds of the secrets under PATH
+move FROM TO        Move a secret to another path
+save                Save the store
+quit                Leave the shell";
+
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    Create(store::Path, store::Entry),
+    Read(store::Path),
+    Update(store::Path, store::Entry),
+    Delete(store::Path),
+    List(store::Path),
+    Move(store::Path, store::Path),
+    Save,
+    Quit,
+    Help,
+}
+
+impl Command {
+    fn parse(line: &str) -> anyhow::Result<Option<Self>> {
+        let (command, rest) = split_token(line.trim_start());
+        if command.is_empty() {
+            return Ok(None);
+        }
+
+        let path = |string: &str| -> anyhow::Result<store::Path> { Ok(string.parse()?) };
+
+        let command = match command {
+            "create" | "update" => {
+                let (target, value) = split_token(rest);
+                let target = path(target)?;
+                let value = super::args::parse_entry(value)?;
+                if command == "create" {
+                    Self::Create(target, value)
+                } else {
+                    Self::Update(target, value)
+                }
+            }
+            "read" => Self::Read(path(expect_end(rest)?)?),
+            "delete" => Self::Delete(path(expect_end(rest)?)?),
+            "list" => Self::List(path(expect_end(rest)?)?),
+            "move" => {
+                let (from, to) = split_token(rest);
+                Self::Move(path(from)?, path(expect_end(to)?)?)
+            }
+            "save" | "quit" | "exit" | "help" if !rest.is_empty() => {
+                anyhow::bail!("Unexpected argument: {}", rest)
+            }
+            "save" => Self::Save,
+            "quit" | "exit" => Self::Quit,
+            "help" => Self::Help,
+            unknown => anyhow::bail!("Unknown command: {}", unknown),
+        };
+
+        Ok(Some(command))
+    }
+
+    /// The command as it is safe to remember, leaving out any secret value
+    fn redacted(&self) -> String {
+        match self {
+            Self::Create(path, _) => format!("create {path}"),
+            Self::Read(path) => format!("read {path}"),
+            Self::Update(path, _) => format!("update {path}"),
+            Self::Delete(path) => format!("delete {path}"),
+            Self::List(path) if path.is_empty() => String::from("list"),
+            Self::List(path) => format!("list {path}"),
+            Self::Move(from, to) => format!("move {from} {to}"),
+            Self::Save => String::from("save"),
+            Self::Quit => String::from("quit"),
+            Self::Help => String::from("help"),
+        }
+    }
+}
+
+/// Splits the first token, which ends at whitespace outside quotes and escapes
+fn split_token(string: &str) -> (&str, &str) {
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for (index, c) in string.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            quoted = !quoted;
+        } else if c.is_whitespace() && !quoted {
+            return (&string[..index], string[index..].trim_start());
+        }
+    }
+
+    (string, "")
+}
+
+fn expect_end(rest: &str) -> anyhow::Result<&str> {
+    let (token, rest) = split_token(rest);
+    if rest.is_empty() {
+        Ok(token)
+    } else {
+        Err(anyhow::anyhow!("Unexpected argument: {}", rest))
+    }
+}
+
+/// Full lines that complete the last token of `line`
+fn candidates(store: &store::Store, line: &str) -> Vec<String> {
+    let (command, rest) = split_token(line);
+    if rest.is_empty() && !line.ends_with(char::is_whitespace) {
+        return COMMANDS
+            .iter()
+            .filter(|candidate| candidate.starts_with(command))
+            .map(|candidate| format!("{candidate} "))
+            .collect();
+    }
+
+    let path_arguments = match command {
+        "read" | "delete" | "list" | "create" | "update" => 1,
+        "move" => 2,
+        _ => 0,
+    };
+
+    // Find the argument being typed
+    let mut arguments = 0;
+    let mut start = line.len() - rest.len();
+    let mut rest = rest;
+    loop {
+        let (token, remaining) = split_token(rest);
+        arguments += 1;
+        if remaining.is_empty() && token.len() == rest.len() {
+            break;
+        }
+        start = line.len() - remaining.len();
+        rest = remaining;
+    }
+    if arguments > path_arguments {
+        return Vec::new();
+    }
+
+    let partial = &line[start..];
+    let (parent, name) = match last_separator(partial) {
+        Some(index) => (&partial[..index], &partial[index + 1..]),
+        None => ("", partial),
+    };
+
+    let Ok(parent_path) = parent.parse::<store::Path>() else {
+        return Vec::new();
+    };
+    let nested = if parent_path.is_empty() {
+        store
+    } else if let Ok(store::Entry::Nested(nested)) = store.read_at(&parent_path) {
+        nested
+    } else {
+        return Vec::new();
+    };
+
+    let mut names = nested
+        .iter()
+        .map(|(child, entry)| {
+            let formatted = std::iter::once(child.as_str())
+                .collect::<store::Path>()
+                .to_string();
+            let suffix = if matches!(entry, store::Entry::Nested(_)) {
+                "."
+            } else {
+                " "
+            };
+            (formatted, suffix)
+        })
+        .filter(|(formatted, _)| formatted.starts_with(name))
+        .collect::<Vec<_>>();
+    names.sort();
+
+    let prefix = &line[..line.len() - name.len()];
+    names
+        .into_iter()
+        .map(|(formatted, suffix)| format!("{prefix}{formatted}{suffix}"))
+        .collect()
+}
+
+/// Index of the last `.` separating path segments, outside quotes and escapes
+fn last_separator(path: &str) -> Option<usize> {
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut last = None;
+
+    for (index, c) in path.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            quoted = !quoted;
+        } else if c == '.' && !quoted {
+            last = Some(index);
+        }
+    }
+
+    last
+}
+
+/// State of a shell session
+struct Session<'a> {
+    store: &'a mut store::Store,
+    modified: bool,
+    warned: bool,
+}
+
+impl Session<'_> {
+    /// Runs a command, returning whether the shell should exit
+    fn execute<F>(&mut self, command: Command, save: &mut F) -> anyhow::Result<bool>
+    where
+        F: FnMut(&store::Store) -> anyhow::Result<()>,
+    {
+        let quitting = command == Command::Quit;
+
+        match command {
+            Command::Create(path, entry) => {
+                self.store.create_at(&path, entry)?;
+                self.modified = true;
+            }
+            Command::Read(path) => {
+                let entry = self.store.read_at(&path)?;
+                super::output::print(&super::output::format(
+                    entry,
+                    &path,
+                    super::args::ValueFormat::Json,
+                )?)?;
+                self.store.metadata_at_mut(&path)?.mark_read();
+            }
+            Command::Update(path, entry) => {
+                self.store.update_at(&path, entry)?;
+                self.modified = true;
+            }
+            Command::Delete(path) => {
+                self.store.delete_at(&path, store::Prune::EmptyParents)?;
+                self.modified = true;
+            }
+            Command::List(path) => {
+                for line in super::listing::list(self.store, &path, 1)? {
+                    println!("{line}");
+                }
+            }
+            Command::Move(from, to) => {
+                self.store.move_at(&from, &to, store::Prune::EmptyParents)?;
+                self.modified = true;
+            }
+            Command::Save => {
+                save(self.store)?;
+                self.modified = false;
+            }
+            Command::Quit => {
+                if self.modified && !self.warned {
+                    self.warned = true;
+                    eprintln!(
+                        "There are unsaved changes. Use `save`, or `quit` again to discard them"
+                    );
+                    return Ok(false);
+                }
+                return Ok(true);
+            }
+            Command::Help => println!("{HELP}"),
+        }
+
+        if !quitting {
+            self.warned = false;
+        }
+        Ok(false)
+    }
+}
+
+/// Runs the shell until the user quits, calling `save` to persist the store
+pub fn run<F>(store: &mut store::Store, mut save: F) -> anyhow::Result<()>
+where
+    F: FnMut(&store::Store) -> anyhow::Result<()>,
+{
+    use rucline::prompt::{Builder, Prompt};
+
+    let mut history: Vec<String> = Vec::new();
+    let mut session = Session {
+        store,
+        modified: false,
+        warned: false,
+    };
+
+    loop {
+        let outcome = {
+            let store = &*session.store;
+            let history = &history;
+            Prompt::from("passifier> ")
+                .erase_after_read(true)
+                .completer_fn(move |buffer| {
+                    let line = buffer.as_str();
+                    if line.is_empty() {
+                        return None;
+                    }
+
+                    history
+                        .iter()
+                        .rev()
+                        .find(|previous| previous.starts_with(line))
+                        .cloned()
+                        .or_else(|| candidates(store, line).into_iter().next())
+                        .map(|candidate| candidate[line.len()..].to_string())
+                })
+                .suggester_fn(move |buffer| candidates(store, buffer.as_str()))
+                .read_line()
+                .map_err(|error| anyhow::anyhow!("{}", error))?
+        };
+
+        let line = match outcome {
+            rucline::Outcome::Accepted(line) => line,
+            rucline::Outcome::Canceled(_) => continue,
+        };
+
+        match Command::parse(&line) {
+            Ok(Some(command)) => {
+                let redacted = command.redacted();
+                println!("passifier> {redacted}");
+                if history.last() != Some(&redacted) {
+                    history.push(redacted);
+                }
+
+                match session.execute(command, &mut save) {
+                    Ok(true) => return Ok(()),
+                    Ok(false) => {}
+                    Err(error) => eprintln!("Error: {error}"),
+                }
+            }
+            Ok(None) => {}
+            Err(error) => eprintln!("Error: {error}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Command;
+
+    fn make_store() -> store::Store {
+        serde_json::from_str(
+            r#"{
+                 "hosts": {
+                   "example.com": {
+                     "password": "secret"
+                   },
+                   "other": "value"
+                 },
+                 "hidden": "value"
+               }"#,
+        )
+        .unwrap()
+    }
+
+    fn path(string: &str) -> store::Path {
+        string.parse().unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Command::parse("").unwrap(), None);
+        assert_eq!(Command::parse("   ").unwrap(), None);
+        assert_eq!(
+            Command::parse(r#"create hosts."example.com".user root user"#).unwrap(),
+            Some(Command::Create(
+                path(r#"hosts."example.com".user"#),
+                store::Entry::String(String::from("root user").into())
+            ))
+        );
+        assert_eq!(
+            Command::parse(r#"update "with space".key [1, 2]"#).unwrap(),
+            Some(Command::Update(
+                path(r#""with space".key"#),
+                store::Entry::Binary(vec![1, 2].into())
+            ))
+        );
+        assert_eq!(
+            Command::parse("read /hosts/example.com").unwrap(),
+            Some(Command::Read(path("/hosts/example.com")))
+        );
+        assert_eq!(
+            Command::parse("list").unwrap(),
+            Some(Command::List(store::Path::new()))
+        );
+        assert_eq!(
+            Command::parse("move a b.c").unwrap(),
+            Some(Command::Move(path("a"), path("b.c")))
+        );
+        assert_eq!(Command::parse(" quit ").unwrap(), Some(Command::Quit));
+
+        assert!(Command::parse("bla").is_err());
+        assert!(Command::parse("read a b").is_err());
+        assert!(Command::parse("create a").is_err());
+        assert!(Command::parse("save now").is_err());
+    }
+
+    #[test]
+    fn redacted() {
+        let command = Command::parse("create hosts.db hunter2").unwrap().unwrap();
+        assert_eq!(command.redacted(), "create hosts.db");
+
+        let command = Command::parse(r#"update "a b".c {"user": "hunter2"}"#)
+            .unwrap()
+            .unwrap();
+        assert_eq!(command.redacted(), r#"update "a b".c"#);
+
+        let command = Command::parse("move a /b/c.d").unwrap().unwrap();
+        assert_eq!(command.redacted(), r#"move a b."c.d""#);
+    }
+
+    #[test]
+    fn candidates() {
+        let store = make_store();
+
+        assert_eq!(super::candidates(&store, "re"), ["read "]);
+        assert_eq!(
+            super::candidates(&store, "read "),
+            ["read hidden ", "read hosts."]
+        );
+        assert_eq!(super::candidates(&store, "read ho"), ["read hosts."]);
+        assert_eq!(
+            super::candidates(&store, "read hosts."),
+            ["read hosts.\"example.com\".", "read hosts.other "]
+        );
+        assert_eq!(
+            super::candidates(&store, "read hosts.\"ex"),
+            ["read hosts.\"example.com\"."]
+        );
+        assert_eq!(
+            super::candidates(&store, "move hidden hosts.o"),
+            ["move hidden hosts.other "]
+        );
+        assert!(super::candidates(&store, "read hidden.").is_empty());
+        assert!(super::candidates(&store, "read hidden x").is_empty());
+        assert!(super::candidates(&store, "create hidden ").is_empty());
+    }
+
+    #[test]
+    fn execute() {
+        let mut store = make_store();
+        let mut saved = Vec::new();
+        let mut save = |store: &store::Store| {
+            saved.push(store.clone());
+            Ok(())
+        };
+
+        let mut session = super::Session {
+            store: &mut store,
+            modified: false,
+            warned: false,
+        };
+
+        macro_rules! run {
+            ($line:literal) => {
+                session.execute(Command::parse($line).unwrap().unwrap(), &mut save)
+            };
+        }
+
+        assert!(run!("quit").unwrap());
+
+        let other = "hosts.other".parse().unwrap();
+        session.store.metadata_at_mut(&other).unwrap().notes = Some(String::from("note"));
+        session
+            .store
+            .update_at(&other, store::Entry::String(String::from("value").into()))
+            .unwrap();
+
+        assert!(!run!("move hosts.other moved").unwrap());
+        assert!(session.modified);
+        assert_eq!(
+            session
+                .store
+                .metadata_at(&"moved".parse().unwrap())
+                .unwrap()
+                .notes
+                .as_deref(),
+            Some("note")
+        );
+        assert_eq!(
+            session
+                .store
+                .history_at(&"moved".parse().unwrap())
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(run!("move hosts hosts.inner").is_err());
+
+        // Quitting with unsaved changes asks for confirmation
+        assert!(!run!("quit").unwrap());
+        assert!(session.warned);
+        assert!(run!("quit").unwrap());
+
+        // Any other command resets the confirmation
+        session.warned = false;
+        assert!(!run!("quit").unwrap());
+        assert!(!run!("list").unwrap());
+        assert!(!run!("quit").unwrap());
+
+        assert!(!run!("save").unwrap());
+        assert!(!session.modified);
+        assert!(run!("quit").unwrap());
+
+        assert_eq!(saved.len(), 1);
+        assert_eq!(
+            saved[0],
+            serde_json::from_str(
+                r#"{
+                     "hosts": {
+                       "example.com": {
+                         "password": "secret"
+                       }
+                     },
+                     "moved": "value",
+                     "hidden": "value"
+                   }"#
+            )
+            .unwrap()
+        );
+    }
+}